@@ -1,5 +1,8 @@
+use crate::distrobox::capabilities::DEFAULT_SETUP_MARKER;
 use crate::distrobox::parser::config::get_distrobox_config;
+use crate::oci::container_runtime::{new_container_runtime, ContainerRuntime};
 use lazy_static::lazy_static;
+use std::path::PathBuf;
 use std::sync::Mutex;
 
 use std::process::Command;
@@ -8,16 +11,39 @@ lazy_static! {
     static ref DISTROBOX_MODE: Mutex<bool> = Mutex::new(true);
     static ref DISTROBOX_BOOST_IMAGE_PREFIX: Mutex<String> =
         Mutex::new("distrobox-boost".to_string());
+    static ref DISTROBOX_SETUP_MARKER: Mutex<Option<String>> =
+        Mutex::new(Some(DEFAULT_SETUP_MARKER.to_string()));
+    static ref DISTROBOX_ASSEMBLE_PULL: Mutex<bool> = Mutex::new(true);
 }
 
 pub fn get_distrobox_mode() -> bool {
-    DISTROBOX_MODE.lock().unwrap().clone()
+    *DISTROBOX_MODE.lock().unwrap()
 }
 
 pub fn set_distrobox_mode(mode: bool) {
     *DISTROBOX_MODE.lock().unwrap() = mode;
 }
 
+/// File created at the end of a distrobox image so distrobox-init skips its setup,
+/// `None` when the installed distrobox-init does not check one
+pub fn get_distrobox_setup_marker() -> Option<String> {
+    DISTROBOX_SETUP_MARKER.lock().unwrap().clone()
+}
+
+pub fn set_distrobox_setup_marker(marker: Option<String>) {
+    *DISTROBOX_SETUP_MARKER.lock().unwrap() = marker;
+}
+
+/// Whether built containers get `pull=false`, off when the installed distrobox
+/// does not read `pull` in assemble files
+pub fn get_distrobox_assemble_pull() -> bool {
+    *DISTROBOX_ASSEMBLE_PULL.lock().unwrap()
+}
+
+pub fn set_distrobox_assemble_pull(assemble_pull: bool) {
+    *DISTROBOX_ASSEMBLE_PULL.lock().unwrap() = assemble_pull;
+}
+
 pub fn get_distrobox_boost_image_prefix() -> String {
     DISTROBOX_BOOST_IMAGE_PREFIX.lock().unwrap().clone()
 }
@@ -26,42 +52,80 @@ pub fn set_distrobox_boost_image_prefix(image_prefix: &str) {
     *DISTROBOX_BOOST_IMAGE_PREFIX.lock().unwrap() = image_prefix.to_string();
 }
 
+#[cfg(test)]
 pub fn get_distrobox_boost_test_image_prefix() -> String {
     format!("{}-test", &get_distrobox_boost_image_prefix())
 }
 
+/// Local state of the images under the current prefix, e.g. package manifests and pins
+pub fn get_distrobox_boost_data_dir() -> PathBuf {
+    let data_dir = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".local/share"),
+    };
+    data_dir
+        .join("distrobox-boost")
+        .join(get_distrobox_boost_image_prefix())
+}
+
+/// User overrides such as package manager definitions
+pub fn get_distrobox_boost_config_dir() -> PathBuf {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".config"),
+    };
+    config_dir.join("distrobox-boost")
+}
+
+pub fn get_distrobox_boost_manifest_dir() -> PathBuf {
+    get_distrobox_boost_data_dir().join("manifests")
+}
+
+pub fn get_distrobox_boost_pin_state_path() -> PathBuf {
+    get_distrobox_boost_data_dir().join("pins.json")
+}
+
 fn command_exists(command: &str) -> bool {
     let os = std::env::consts::OS;
     let output = if os == "windows" {
         Command::new("cmd")
-            .args(&["/C", "where", command])
+            .args(["/C", "where", command])
             .output()
             .expect("Failed to execute command")
     } else {
         Command::new("sh")
-            .args(&["-c", &format!("command -v {}", command)])
+            .args(["-c", &format!("command -v {}", command)])
             .output()
             .expect("Failed to execute command")
     };
     output.status.success()
 }
 
-pub fn get_container_manager() -> String {
+/// Engine set in the distrobox config, podman or docker when it is "autodetect"
+pub fn find_container_manager() -> Option<String> {
     let config = get_distrobox_config();
-    fn autodetect() -> String {
+    fn autodetect() -> Option<String> {
         if command_exists("podman") {
-            "podman".to_string()
+            Some("podman".to_string())
         } else if command_exists("docker") {
-            "docker".to_string()
+            Some("docker".to_string())
         } else {
-            panic!("No container manager found")
+            None
         }
     }
     match config.get("container_manager") {
         None => autodetect(),
         Some(container_manager) => match container_manager.as_str() {
             "autodetect" => autodetect(),
-            _ => container_manager.clone(),
+            _ => Some(container_manager.clone()),
         },
     }
 }
+
+pub fn get_container_manager() -> String {
+    find_container_manager().expect("No container manager found")
+}
+
+pub fn get_container_runtime() -> Box<dyn ContainerRuntime> {
+    new_container_runtime(&get_container_manager())
+}
@@ -1,4 +1,5 @@
 use crate::distrobox::parser::assemble::ContainerAssembleData;
+use crate::utils::digest::sha256_hex;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
@@ -12,6 +13,28 @@ pub struct ContainerNode {
     pub children: Vec<ContainerNode>,
 }
 
+/// Name of the virtual parent for a container whose image is not another container;
+/// containers that build one Dockerfile with different options get different parents
+fn get_virtual_parent_name(data: &ContainerAssembleData) -> String {
+    let options = [
+        data.dockerfile_context.clone().unwrap_or_default(),
+        data.dockerfile_build_args
+            .clone()
+            .unwrap_or_default()
+            .join("\n"),
+        data.dockerfile_target.clone().unwrap_or_default(),
+        data.dockerfile_secret
+            .clone()
+            .unwrap_or_default()
+            .join("\n"),
+    ];
+    if !data.image.starts_with("dockerfile://") || options.iter().all(String::is_empty) {
+        return data.image.clone();
+    }
+    let key = sha256_hex(options.join("\0").as_bytes());
+    format!("{}#{}", data.image, &key[..12])
+}
+
 pub fn distrobox_assemble_to_trees(
     container_assemble_data_map: &HashMap<String, ContainerAssembleData>,
 ) -> Vec<ContainerNode> {
@@ -21,7 +44,9 @@ pub fn distrobox_assemble_to_trees(
         container_assemble_data: ContainerAssembleData,
         children: Vec<Rc<RefCell<ContainerNodeRef>>>,
     }
-    let mut node_refs: BTreeMap<&str, Rc<RefCell<ContainerNodeRef>>> = BTreeMap::new();
+    let mut node_refs: BTreeMap<String, Rc<RefCell<ContainerNodeRef>>> = BTreeMap::new();
+    // container name to the name of its parent node
+    let mut parent_names: BTreeMap<String, String> = BTreeMap::new();
 
     // First pass: create nodes and establish parent-child relationships
     for (container_name, container_assemble_data) in container_assemble_data_map {
@@ -33,32 +58,43 @@ pub fn distrobox_assemble_to_trees(
             container_assemble_data: container_assemble_data.clone(),
             children: vec![],
         }));
-        node_refs.insert(&container_name, node_ref.clone());
-
-        // Only add the parent node to the map if it doesn't exist yet
-        if !container_assemble_data_map.contains_key(image_name) {
-            let container_assemble_data = ContainerAssembleData {
-                image: image_name.clone(),
-                ..Default::default()
-            };
-            let parent_node_ref = Rc::new(RefCell::new(ContainerNodeRef {
-                container_name: image_name.clone(),
+        node_refs.insert(container_name.clone(), node_ref.clone());
+        if container_name == image_name {
+            continue;
+        }
+        if container_assemble_data_map.contains_key(image_name) {
+            parent_names.insert(container_name.clone(), image_name.clone());
+            continue;
+        }
+
+        // a Dockerfile is built for the virtual parent, with the options its children share
+        let parent_name = get_virtual_parent_name(container_assemble_data);
+        parent_names.insert(container_name.clone(), parent_name.clone());
+        let container_assemble_data = ContainerAssembleData {
+            image: image_name.clone(),
+            dockerfile_context: container_assemble_data.dockerfile_context.clone(),
+            dockerfile_build_args: container_assemble_data.dockerfile_build_args.clone(),
+            dockerfile_target: container_assemble_data.dockerfile_target.clone(),
+            dockerfile_secret: container_assemble_data.dockerfile_secret.clone(),
+            ..Default::default()
+        };
+        node_refs.entry(parent_name.clone()).or_insert_with(|| {
+            Rc::new(RefCell::new(ContainerNodeRef {
+                container_name: parent_name,
                 virtual_container: true,
                 container_assemble_data,
                 children: vec![],
-            }));
-            node_refs.insert(image_name, parent_node_ref);
-        }
+            }))
+        });
     }
 
     // Second pass: flatten the structure
-    for (container_name, node_ref) in &node_refs {
-        let image_name = node_ref.borrow().container_assemble_data.image.clone();
-        if container_name != &image_name {
-            let parent_node_name = image_name.as_str();
-            let parent_node = node_refs.get(parent_node_name).unwrap();
-            parent_node.borrow_mut().children.push(Rc::clone(node_ref));
-        }
+    for (container_name, parent_name) in &parent_names {
+        let node_ref = &node_refs[container_name];
+        node_refs[parent_name]
+            .borrow_mut()
+            .children
+            .push(Rc::clone(node_ref));
     }
 
     // Recursive function to convert ContainerNodeRef into ContainerNode
@@ -86,9 +122,7 @@ pub fn distrobox_assemble_to_trees(
     // Filter out non-root nodes and convert to ContainerNode
     node_refs
         .values()
-        .filter(|node_ref| {
-            node_ref.borrow().container_name == node_ref.borrow().container_assemble_data.image
-        })
+        .filter(|node_ref| !parent_names.contains_key(&node_ref.borrow().container_name))
         .map(|node_ref| {
             // Convert each root ContainerNodeRef into ContainerNode
             convert_to_container_node(node_ref)
@@ -127,19 +161,27 @@ mod tests {
     #[test]
     fn test_distrobox_assemble_to_trees() {
         let mut container_assemble_data_map = HashMap::new();
-        let mut data1 = ContainerAssembleData::default();
-        data1.image = "base".to_string();
+        let data1 = ContainerAssembleData {
+            image: "base".to_string(),
+            ..Default::default()
+        };
         container_assemble_data_map.insert("container1".to_string(), data1);
-        let mut data3 = ContainerAssembleData::default();
         // test sort
-        data3.image = "container1".to_string();
+        let data3 = ContainerAssembleData {
+            image: "container1".to_string(),
+            ..Default::default()
+        };
         container_assemble_data_map.insert("container3".to_string(), data3);
-        let mut data2 = ContainerAssembleData::default();
-        data2.image = "container1".to_string();
+        let data2 = ContainerAssembleData {
+            image: "container1".to_string(),
+            ..Default::default()
+        };
         container_assemble_data_map.insert("container2".to_string(), data2);
 
-        let mut data4 = ContainerAssembleData::default();
-        data4.image = "base1".to_string();
+        let data4 = ContainerAssembleData {
+            image: "base1".to_string(),
+            ..Default::default()
+        };
         container_assemble_data_map.insert("container4".to_string(), data4);
 
         let container_tree = distrobox_assemble_to_trees(&container_assemble_data_map);
@@ -164,10 +206,56 @@ mod tests {
         assert_eq!(container_tree[1].children[0].container_name, "container4");
     }
 
+    #[test]
+    fn test_distrobox_assemble_to_trees_dockerfile_options() {
+        let dockerfile_data = |build_arg: &str| ContainerAssembleData {
+            image: "dockerfile://Containerfile".to_string(),
+            dockerfile_build_args: Some(vec![build_arg.to_string()]),
+            ..Default::default()
+        };
+        let mut container_assemble_data_map = HashMap::new();
+        container_assemble_data_map.insert("a".to_string(), dockerfile_data("VERSION=1"));
+        container_assemble_data_map.insert("b".to_string(), dockerfile_data("VERSION=2"));
+        container_assemble_data_map.insert("c".to_string(), dockerfile_data("VERSION=1"));
+
+        let container_tree = distrobox_assemble_to_trees(&container_assemble_data_map);
+
+        // one build per set of options, shared by the containers that use it
+        assert_eq!(container_tree.len(), 2);
+        for tree in &container_tree {
+            assert!(tree.virtual_container);
+            assert!(tree
+                .container_name
+                .starts_with("dockerfile://Containerfile#"));
+            assert_eq!(
+                tree.container_assemble_data.image,
+                "dockerfile://Containerfile"
+            );
+            let build_args = tree.container_assemble_data.dockerfile_build_args.clone();
+            let children: Vec<&str> = tree
+                .children
+                .iter()
+                .map(|child| child.container_name.as_str())
+                .collect();
+            if build_args == Some(vec!["VERSION=1".to_string()]) {
+                assert_eq!(children, ["a", "c"]);
+            } else {
+                assert_eq!(build_args, Some(vec!["VERSION=2".to_string()]));
+                assert_eq!(children, ["b"]);
+            }
+        }
+        assert_ne!(
+            container_tree[0].container_name,
+            container_tree[1].container_name
+        );
+    }
+
     #[test]
     fn test_trees_to_distrobox_assemble() {
-        let mut data1 = ContainerAssembleData::default();
-        data1.image = "Image1".to_string();
+        let data1 = ContainerAssembleData {
+            image: "Image1".to_string(),
+            ..Default::default()
+        };
         let node1 = ContainerNode {
             container_name: String::from("Node1"),
             virtual_container: false,
@@ -175,8 +263,10 @@ mod tests {
             children: vec![],
         };
 
-        let mut data2 = ContainerAssembleData::default();
-        data2.image = "Image2".to_string();
+        let data2 = ContainerAssembleData {
+            image: "Image2".to_string(),
+            ..Default::default()
+        };
         let node2 = ContainerNode {
             container_name: String::from("Node2"),
             virtual_container: false,
@@ -184,8 +274,10 @@ mod tests {
             children: vec![],
         };
 
-        let mut data3 = ContainerAssembleData::default();
-        data3.image = "Image3".to_string();
+        let data3 = ContainerAssembleData {
+            image: "Image3".to_string(),
+            ..Default::default()
+        };
         let node3 = ContainerNode {
             container_name: String::from("Node3"),
             virtual_container: false,
@@ -194,8 +286,10 @@ mod tests {
         };
 
         let children = vec![node1, node2, node3];
-        let mut data4 = ContainerAssembleData::default();
-        data4.image = "Image4".to_string();
+        let data4 = ContainerAssembleData {
+            image: "Image4".to_string(),
+            ..Default::default()
+        };
         let node4 = ContainerNode {
             container_name: String::from("Node4"),
             virtual_container: false,
@@ -203,16 +297,20 @@ mod tests {
             children,
         };
 
-        let mut data6 = ContainerAssembleData::default();
-        data6.image = "Image6".to_string();
+        let data6 = ContainerAssembleData {
+            image: "Image6".to_string(),
+            ..Default::default()
+        };
         let node6 = ContainerNode {
             container_name: String::from("Node6"),
             virtual_container: false,
             container_assemble_data: data6,
             children: vec![],
         };
-        let mut data5 = ContainerAssembleData::default();
-        data5.image = "Image5".to_string();
+        let data5 = ContainerAssembleData {
+            image: "Image5".to_string(),
+            ..Default::default()
+        };
         let node5 = ContainerNode {
             container_name: String::from("Node5"),
             virtual_container: true,
@@ -224,16 +322,26 @@ mod tests {
 
         let result = trees_to_distrobox_assemble(&trees);
 
-        let mut expected_data1 = ContainerAssembleData::default();
-        expected_data1.image = "Image1".to_string();
-        let mut expected_data2 = ContainerAssembleData::default();
-        expected_data2.image = "Image2".to_string();
-        let mut expected_data3 = ContainerAssembleData::default();
-        expected_data3.image = "Image3".to_string();
-        let mut expected_data4 = ContainerAssembleData::default();
-        expected_data4.image = "Image4".to_string();
-        let mut expected_data6 = ContainerAssembleData::default();
-        expected_data6.image = "Image6".to_string();
+        let expected_data1 = ContainerAssembleData {
+            image: "Image1".to_string(),
+            ..Default::default()
+        };
+        let expected_data2 = ContainerAssembleData {
+            image: "Image2".to_string(),
+            ..Default::default()
+        };
+        let expected_data3 = ContainerAssembleData {
+            image: "Image3".to_string(),
+            ..Default::default()
+        };
+        let expected_data4 = ContainerAssembleData {
+            image: "Image4".to_string(),
+            ..Default::default()
+        };
+        let expected_data6 = ContainerAssembleData {
+            image: "Image6".to_string(),
+            ..Default::default()
+        };
 
         assert_eq!(result.len(), 5);
         let res1 = result.get("Node1").unwrap();
@@ -1,3 +1,5 @@
+pub mod definitions;
 pub mod distrobox_requirements;
 pub mod os_info;
+pub mod package_diff;
 pub mod package_manager;
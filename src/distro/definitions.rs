@@ -0,0 +1,384 @@
+// package managers and distrobox requirements, compiled in from the toml files
+// next to this module and overridable from the user config dir
+
+use crate::distro::distrobox_requirements::compare_versions;
+use crate::oci::container_command::ContainerCommand;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+pub const PACKAGE_MANAGERS_FILE: &str = "package_managers.toml";
+pub const REQUIREMENTS_FILE: &str = "distrobox_requirements.toml";
+
+const BUILTIN_PACKAGE_MANAGERS: &str = include_str!("package_managers.toml");
+const BUILTIN_REQUIREMENTS: &str = include_str!("distrobox_requirements.toml");
+
+/// Parser for the output of the `list` command
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ListFormat {
+    Apk,
+    Pacman,
+    Yum,
+    Apt,
+    Zypper,
+    Xbps,
+    Emerge,
+    Eopkg,
+    Swupd,
+    Slackpkg,
+    Nix,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PackageManagerDefinition {
+    pub distros: Vec<String>,
+    pub update: ContainerCommand,
+    pub upgrade: ContainerCommand,
+    /// The "{packages}" argument is replaced by the package names
+    pub install: Vec<String>,
+    /// Prepended to package names without a '.', e.g. "nixpkgs."
+    #[serde(default)]
+    pub attribute_prefix: Option<String>,
+    /// Allowed in package names besides ASCII letters and digits
+    #[serde(default = "default_package_chars")]
+    pub package_chars: String,
+    pub list: ContainerCommand,
+    pub list_format: ListFormat,
+}
+
+fn default_package_chars() -> String {
+    "+-._".to_string()
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RequirementDefinition {
+    pub distro: String,
+    /// VERSION_ID range, `from_version` included and `until_version` excluded
+    #[serde(default)]
+    pub from_version: Option<String>,
+    #[serde(default)]
+    pub until_version: Option<String>,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    #[serde(default)]
+    pub optional_packages: Vec<String>,
+    /// Run by `sh -c` after the optional packages, a failure is ignored
+    #[serde(default)]
+    pub optional_scripts: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RequirementsFile {
+    #[serde(default)]
+    requirements: Vec<RequirementDefinition>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Definitions {
+    pub package_managers: BTreeMap<String, PackageManagerDefinition>,
+    /// Tried in order, the first match wins
+    pub requirements: Vec<RequirementDefinition>,
+}
+
+#[derive(Debug)]
+pub struct DefinitionError {
+    pub source: String,
+    pub message: String,
+}
+
+impl fmt::Display for DefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.source, self.message.trim_end())
+    }
+}
+
+impl std::error::Error for DefinitionError {}
+
+fn definition_error(source: &str, message: impl Into<String>) -> DefinitionError {
+    DefinitionError {
+        source: source.to_string(),
+        message: message.into(),
+    }
+}
+
+pub fn parse_package_managers(
+    content: &str,
+    source: &str,
+) -> Result<BTreeMap<String, PackageManagerDefinition>, DefinitionError> {
+    let package_managers: BTreeMap<String, PackageManagerDefinition> =
+        toml::from_str(content).map_err(|e| definition_error(source, e.to_string()))?;
+
+    let mut distro_map: HashMap<&str, &str> = HashMap::new();
+    for (name, package_manager) in &package_managers {
+        if !package_manager
+            .install
+            .iter()
+            .any(|arg| arg == "{packages}")
+        {
+            return Err(definition_error(
+                source,
+                format!("[{}] install must have a \"{{packages}}\" argument", name),
+            ));
+        }
+        for (key, command) in [
+            ("update", &package_manager.update),
+            ("upgrade", &package_manager.upgrade),
+            ("list", &package_manager.list),
+        ] {
+            if command.is_empty() {
+                return Err(definition_error(
+                    source,
+                    format!("[{}] {} is empty", name, key),
+                ));
+            }
+        }
+        for distro in &package_manager.distros {
+            if let Some(other) = distro_map.insert(distro, name) {
+                return Err(definition_error(
+                    source,
+                    format!(
+                        "distro {} is listed for both {} and {}",
+                        distro, other, name
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(package_managers)
+}
+
+pub fn parse_requirements(
+    content: &str,
+    source: &str,
+) -> Result<Vec<RequirementDefinition>, DefinitionError> {
+    let file: RequirementsFile =
+        toml::from_str(content).map_err(|e| definition_error(source, e.to_string()))?;
+
+    for (i, requirement) in file.requirements.iter().enumerate() {
+        if requirement.distro.is_empty() {
+            return Err(definition_error(
+                source,
+                format!("requirements #{} has an empty distro", i + 1),
+            ));
+        }
+        if let (Some(from), Some(until)) = (&requirement.from_version, &requirement.until_version) {
+            if compare_versions(from, until) != Ordering::Less {
+                return Err(definition_error(
+                    source,
+                    format!(
+                        "requirements #{} ({}): from_version {} is not below until_version {}",
+                        i + 1,
+                        requirement.distro,
+                        from,
+                        until
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(file.requirements)
+}
+
+impl Definitions {
+    pub fn builtin() -> Self {
+        Definitions {
+            package_managers: parse_package_managers(BUILTIN_PACKAGE_MANAGERS, "built-in")
+                .expect("invalid built-in package managers"),
+            requirements: parse_requirements(BUILTIN_REQUIREMENTS, "built-in")
+                .expect("invalid built-in requirements"),
+        }
+    }
+
+    /// User package managers replace the built-in ones with the same name and
+    /// take over their distros, user requirements are tried first
+    pub fn merge(
+        &mut self,
+        package_managers: BTreeMap<String, PackageManagerDefinition>,
+        requirements: Vec<RequirementDefinition>,
+    ) {
+        for package_manager in package_managers.values() {
+            for existing in self.package_managers.values_mut() {
+                existing
+                    .distros
+                    .retain(|distro| !package_manager.distros.contains(distro));
+            }
+        }
+        self.package_managers.extend(package_managers);
+        self.requirements.splice(0..0, requirements);
+    }
+
+    pub fn get_package_manager(&self, name: &str) -> Option<&PackageManagerDefinition> {
+        self.package_managers.get(name)
+    }
+
+    /// Name of the package manager that handles `distro`
+    pub fn find_package_manager(&self, distro: &str) -> Option<&str> {
+        self.package_managers
+            .iter()
+            .find(|(_, package_manager)| package_manager.distros.iter().any(|x| x == distro))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+fn read_file(path: &Path) -> Result<Option<String>, DefinitionError> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(definition_error(&path.display().to_string(), e.to_string())),
+    }
+}
+
+/// Built-in definitions with the overrides found in `config_dir`
+pub fn load_definitions(config_dir: &Path) -> Result<Definitions, DefinitionError> {
+    let mut definitions = Definitions::builtin();
+
+    let path = config_dir.join(PACKAGE_MANAGERS_FILE);
+    let package_managers = match read_file(&path)? {
+        Some(content) => parse_package_managers(&content, &path.display().to_string())?,
+        None => BTreeMap::new(),
+    };
+    let path = config_dir.join(REQUIREMENTS_FILE);
+    let requirements = match read_file(&path)? {
+        Some(content) => parse_requirements(&content, &path.display().to_string())?,
+        None => vec![],
+    };
+
+    definitions.merge(package_managers, requirements);
+    Ok(definitions)
+}
+
+lazy_static! {
+    static ref DEFINITIONS: Mutex<Arc<Definitions>> = Mutex::new(Arc::new(Definitions::builtin()));
+}
+
+pub fn get_definitions() -> Arc<Definitions> {
+    DEFINITIONS.lock().unwrap().clone()
+}
+
+pub fn set_definitions(definitions: Definitions) {
+    *DEFINITIONS.lock().unwrap() = Arc::new(definitions);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_definitions() {
+        let definitions = Definitions::builtin();
+        assert_eq!(definitions.package_managers.len(), 12);
+        assert_eq!(definitions.find_package_manager("ubuntu"), Some("apt"));
+        assert_eq!(definitions.find_package_manager("rocky"), Some("yum"));
+        assert_eq!(
+            definitions.get_package_manager("dnf").unwrap().list_format,
+            ListFormat::Yum
+        );
+        assert_eq!(definitions.requirements[0].distro, "alpine");
+    }
+
+    #[test]
+    fn test_load_definitions_overrides() {
+        let config_dir = std::env::temp_dir().join(format!(
+            "distrobox-boost-test-definitions-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::write(
+            config_dir.join(PACKAGE_MANAGERS_FILE),
+            r#"
+[nala]
+distros = ["debian"]
+update = ["nala", "update"]
+upgrade = { shell = "nala update && nala upgrade -y" }
+install = ["nala", "install", "-y", "{packages}"]
+list = ["apt", "list", "--installed"]
+list_format = "apt"
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            config_dir.join(REQUIREMENTS_FILE),
+            r#"
+[[requirements]]
+distro = "debian"
+from_version = "13"
+packages = ["sudo"]
+"#,
+        )
+        .unwrap();
+
+        let definitions = load_definitions(&config_dir).unwrap();
+        std::fs::remove_dir_all(&config_dir).unwrap();
+        assert_eq!(definitions.find_package_manager("debian"), Some("nala"));
+        assert_eq!(definitions.find_package_manager("ubuntu"), Some("apt"));
+        assert_eq!(definitions.requirements[0].packages, vec!["sudo"]);
+        assert_eq!(
+            definitions.requirements.len(),
+            Definitions::builtin().requirements.len() + 1
+        );
+
+        // missing files keep the built-in definitions
+        assert_eq!(
+            load_definitions(&config_dir).unwrap(),
+            Definitions::builtin()
+        );
+    }
+
+    #[test]
+    fn test_parse_definitions_errors() {
+        let error = parse_package_managers(
+            "[apt]\ndistros = [\"debian\"]\nupdate = [\"true\"]\nupgrade = [\"true\"]\ninstal = [\"apt-get\", \"install\", \"{packages}\"]\nlist = [\"true\"]\nlist_format = \"apt\"\n",
+            "package_managers.toml",
+        )
+        .unwrap_err();
+        assert!(error.to_string().starts_with("package_managers.toml: "));
+        assert!(
+            error.message.contains("unknown field `instal`"),
+            "{}",
+            error
+        );
+
+        let error = parse_package_managers(
+            "[apt]\ndistros = []\nupdate = [\"true\"]\nupgrade = [\"true\"]\ninstall = [\"apt-get\", \"install\", \"{packages}\"]\nlist = [\"true\"]\nlist_format = \"dpkg\"\n",
+            "package_managers.toml",
+        )
+        .unwrap_err();
+        assert!(
+            error.message.contains("unknown variant `dpkg`"),
+            "{}",
+            error
+        );
+
+        let error = parse_package_managers(
+            "[apt]\ndistros = []\nupdate = [\"true\"]\nupgrade = { shel = \"true\" }\ninstall = [\"apt-get\", \"install\", \"{packages}\"]\nlist = [\"true\"]\nlist_format = \"apt\"\n",
+            "package_managers.toml",
+        )
+        .unwrap_err();
+        assert!(error.message.contains("unknown field `shel`"), "{}", error);
+
+        let error = parse_package_managers(
+            "[apt]\ndistros = []\nupdate = [\"true\"]\nupgrade = [\"true\"]\ninstall = [\"apt-get\", \"install\", \"-y\"]\nlist = [\"true\"]\nlist_format = \"apt\"\n",
+            "package_managers.toml",
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.message,
+            "[apt] install must have a \"{packages}\" argument"
+        );
+
+        let error = parse_requirements(
+            "[[requirements]]\ndistro = \"debian\"\nfrom_version = \"12\"\nuntil_version = \"11\"\n",
+            "distrobox_requirements.toml",
+        )
+        .unwrap_err();
+        assert!(error.message.contains("from_version 12"), "{}", error);
+    }
+}
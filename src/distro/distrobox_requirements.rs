@@ -1,324 +1,242 @@
-static PACKAGES_MAP: &[(&'static str, &'static [&'static str])] = &[
-    (
-        "alpine",
-        &[
-            "bc",
-            "curl",
-            "diffutils",
-            "findmnt",
-            "findutils",
-            "gnupg",
-            "less",
-            "lsof",
-            "mount",
-            "umount",
-            "ncurses",
-            "pinentry",
-            "libc-utils",
-            "procps",
-            "shadow",
-            "su-exec",
-            "sudo",
-            "util-linux",
-            "util-linux-misc",
-            "vte3",
-            "wget",
-            "$(apk search -q mesa-dri)",
-            "$(apk search -q mesa-vulkan)",
-            "vulkan-loader",
-        ],
-    ),
-    (
-        "arch",
-        &[
-            "bc",
-            "curl",
-            "diffutils",
-            "findutils",
-            "gnupg",
-            "less",
-            "lsof",
-            "ncurses",
-            "pinentry",
-            "procps-ng",
-            "shadow",
-            "sudo",
-            "time",
-            "util-linux",
-            "wget",
-            "mesa",
-            "opengl-driver",
-            "vulkan-intel",
-            "vte-common",
-            "vulkan-radeon",
-        ],
-    ),
-    (
-        "centos",
-        &[
-            "bc",
-            "curl",
-            "diffutils",
-            "findutils",
-            "gnupg2",
-            "less",
-            "lsof",
-            "ncurses",
-            "pam",
-            "passwd",
-            "pinentry",
-            "procps-ng",
-            "shadow-utils",
-            "sudo",
-            "time",
-            "tzdata",
-            "util-linux",
-            "vte-profile",
-            "wget",
-            "mesa-dri-drivers",
-            "mesa-vulkan-drivers",
-            "vulkan",
-        ],
-    ),
-    (
-        "debian",
-        &[
-            "apt-utils",
-            "bc",
-            "curl",
-            "dialog",
-            "diffutils",
-            "findutils",
-            "gnupg2",
-            "less",
-            "libnss-myhostname",
-            "libvte-2.9*-common",
-            "libvte-common",
-            "lsof",
-            "ncurses-base",
-            "passwd",
-            "pinentry-curses",
-            "procps",
-            "sudo",
-            "time",
-            "util-linux",
-            "wget",
-            "libegl1-mesa",
-            "libgl1-mesa-glx",
-            "libvulkan1",
-            "mesa-vulkan-drivers",
-        ],
-    ),
-    (
-        "fedora",
-        &[
-            "bc",
-            "curl",
-            "diffutils",
-            "dnf-plugins-core",
-            "findutils",
-            "gnupg2",
-            "less",
-            "lsof",
-            "ncurses",
-            "pam",
-            "passwd",
-            "pinentry",
-            "procps-ng",
-            "shadow-utils",
-            "sudo",
-            "time",
-            "tzdata",
-            "util-linux",
-            "vte-profile",
-            "wget",
-            "mesa-dri-drivers",
-            "mesa-vulkan-drivers",
-            "vulkan",
-        ],
-    ),
-    (
-        "opensuse",
-        &[
-            "bc",
-            "curl",
-            "diffutils",
-            "findutils",
-            "gnupg",
-            "less",
-            "libvte-2*",
-            "lsof",
-            "ncurses",
-            "pam",
-            "pam-extra",
-            "pinentry",
-            "procps",
-            "shadow",
-            "sudo",
-            "systemd",
-            "time",
-            "util-linux",
-            "util-linux-systemd",
-            "wget",
-            "Mesa-dri",
-            "libvulkan1",
-            "libvulkan_intel",
-            "libvulkan_radeon",
-        ],
-    ),
-    (
-        "rocky",
-        &[
-            "bc",
-            "curl",
-            "diffutils",
-            "findutils",
-            "gnupg2",
-            "less",
-            "lsof",
-            "ncurses",
-            "pam",
-            "passwd",
-            "pinentry",
-            "procps-ng",
-            "shadow-utils",
-            "sudo",
-            "time",
-            "tzdata",
-            "util-linux",
-            "vte-profile",
-            "wget",
-            "mesa-dri-drivers",
-            "mesa-vulkan-drivers",
-            "vulkan",
-        ],
-    ),
-    (
-        "ubuntu",
-        &[
-            "apt-utils",
-            "bc",
-            "curl",
-            "dialog",
-            "diffutils",
-            "findutils",
-            "gnupg2",
-            "less",
-            "libnss-myhostname",
-            "libvte-2.9*-common",
-            "libvte-common",
-            "lsof",
-            "ncurses-base",
-            "passwd",
-            "pinentry-curses",
-            "procps",
-            "sudo",
-            "time",
-            "util-linux",
-            "wget",
-            "libegl1-mesa",
-            "libgl1-mesa-glx",
-            "libvulkan1",
-            "mesa-vulkan-drivers",
-        ],
-    ),
-];
+use crate::distro::definitions::{get_definitions, RequirementDefinition};
+use crate::distro::os_info::OsRelease;
+use std::cmp::Ordering;
 
-pub fn get_distrobox_packages<'a>(distro_id: &'a str, _distro_version: &'a str) -> Vec<&'a str> {
-    let mut packages = Vec::new();
-    for (distro, pkgs) in PACKAGES_MAP.iter() {
-        if distro_id.contains(distro) {
-            packages = pkgs.to_vec();
+/// Packages distrobox needs in a container, the optional ones are skipped
+/// when the release does not have them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DistroboxPackages {
+    pub packages: Vec<String>,
+    pub optional_packages: Vec<String>,
+    /// Shell snippets run after the optional packages, e.g. to install what a search finds
+    pub optional_scripts: Vec<String>,
+}
+
+/// Compare dotted versions numerically, "9.3" < "10"
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut a_parts = a.split('.');
+    let mut b_parts = b.split('.');
+    loop {
+        match (a_parts.next(), b_parts.next()) {
+            (None, None) => return Ordering::Equal,
+            (a_part, b_part) => {
+                let a_part = a_part.unwrap_or("0");
+                let b_part = b_part.unwrap_or("0");
+                let ordering = match (a_part.parse::<u64>(), b_part.parse::<u64>()) {
+                    (Ok(a_number), Ok(b_number)) => a_number.cmp(&b_number),
+                    _ => a_part.cmp(b_part),
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
         }
     }
-    packages
+}
+
+// a bounded rule is skipped when VERSION_ID is missing
+fn matches(requirement: &RequirementDefinition, id: &str, version_id: Option<&str>) -> bool {
+    if requirement.distro != id {
+        return false;
+    }
+    if requirement.from_version.is_none() && requirement.until_version.is_none() {
+        return true;
+    }
+    let Some(version_id) = version_id else {
+        return false;
+    };
+    requirement
+        .from_version
+        .as_deref()
+        .is_none_or(|from| compare_versions(version_id, from) != Ordering::Less)
+        && requirement
+            .until_version
+            .as_deref()
+            .is_none_or(|until| compare_versions(version_id, until) == Ordering::Less)
+}
+
+/// Requirements of the first distro in ID and ID_LIKE that has a rule for this release
+pub fn get_distrobox_packages(os_release: &OsRelease) -> DistroboxPackages {
+    let definitions = get_definitions();
+    let version_id = os_release.version_id.as_deref();
+    os_release
+        .ids()
+        .find_map(|id| {
+            definitions
+                .requirements
+                .iter()
+                .find(|requirement| matches(requirement, id, version_id))
+        })
+        .map(|requirement| DistroboxPackages {
+            packages: requirement.packages.clone(),
+            optional_packages: requirement.optional_packages.clone(),
+            optional_scripts: requirement.optional_scripts.clone(),
+        })
+        .unwrap_or_default()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::config::{get_container_manager, get_distrobox_boost_test_image_prefix};
-    use crate::oci::command_helper::run_container;
+    use super::*;
+    use crate::config::get_distrobox_boost_test_image_prefix;
+    use crate::distro::os_info::parse_os_release;
+    use crate::distro::package_manager::{
+        generate_install_command_with_optional, get_package_manager,
+    };
+    use crate::oci::fake_runtime::{FakeRuntime, UBUNTU_OS_RELEASE};
     use crate::oci::image_builder::build_image;
 
-    fn test_distrobox_packages(distro_id: &str, base_image: &str) {
-        let container_runner = &get_container_manager();
+    fn test_distrobox_packages(distro_id: &str, os_release: &str) {
+        let runtime = FakeRuntime::new().with_image(distro_id, os_release);
         let image_name = format!("test_{}-distrobox", distro_id);
 
         let result = build_image(
-            container_runner,
+            &runtime,
             &image_name,
-            &base_image,
+            distro_id,
             &None,
-            &vec![],
+            &[],
             &get_distrobox_boost_test_image_prefix(),
             true,
         );
 
         let image_name = match result {
-            Ok(image_name) => {
-                println!("Final image name: {}", image_name);
-                image_name
-            }
-            Err(e) => {
-                println!("Error building image: {:?}", e);
-                panic!("Error building image");
-            }
+            Ok(image_name) => image_name,
+            Err(e) => panic!("Error building image for {}: {:?}", distro_id, e),
         };
 
-        let cmds = vec![
-            "find", "mount", "passwd", "sudo", "useradd", "diff", "pinentry", "wget", "curl",
-            "less", "bc", "time", "lsof",
-        ];
-
-        for cmd in cmds {
-            let check_cmd = format!("command -v {}", cmd);
+        // the requirements of the release are installed before the setup marker
+        let os_release = parse_os_release(os_release).unwrap();
+        let requirements = get_distrobox_packages(&os_release);
+        assert!(!requirements.packages.is_empty(), "{}", distro_id);
+        let install_cmd = generate_install_command_with_optional(
+            &get_package_manager(&os_release),
+            &requirements.packages,
+            &requirements.optional_packages,
+            &requirements.optional_scripts,
+        );
+        let image = runtime.image(&image_name).unwrap();
+        assert_eq!(
+            image.commands[image.commands.len() - 2..],
+            [
+                install_cmd.to_string(),
+                "touch /run/.containersetupdone".to_string()
+            ],
+            "{}",
+            distro_id
+        );
+    }
+    fn get_packages(os_release: &str) -> DistroboxPackages {
+        get_distrobox_packages(&parse_os_release(os_release).unwrap())
+    }
 
-            match run_container(container_runner, "", &image_name, &check_cmd, true) {
-                Ok(output) => assert!(!output.stdout.is_empty()),
-                Err(e) => panic!("Error getting OS info from image {}: {:?}", image_name, e),
-            }
+    #[test]
+    fn test_get_distrobox_packages() {
+        for distro_id in [
+            "clear-linux-os",
+            "gentoo",
+            "nixos",
+            "slackware",
+            "solus",
+            "void",
+        ] {
+            assert!(!get_packages(&format!("ID={}\n", distro_id))
+                .packages
+                .is_empty());
         }
+        assert_eq!(
+            get_packages("ID=kali\nID_LIKE=debian\nVERSION_ID=\"2024.1\"\n"),
+            get_packages("ID=debian\nVERSION_ID=\"12\"\n")
+        );
+        assert_eq!(get_packages("ID=unknown\n"), DistroboxPackages::default());
+    }
+
+    #[test]
+    fn test_get_distrobox_packages_by_version() {
+        let bullseye = get_packages("ID=debian\nVERSION_ID=\"11\"\n");
+        assert!(bullseye
+            .optional_packages
+            .contains(&"libgl1-mesa-glx".to_string()));
+        let bookworm = get_packages("ID=debian\nVERSION_ID=\"12\"\n");
+        assert!(!bookworm
+            .optional_packages
+            .contains(&"libgl1-mesa-glx".to_string()));
+        assert!(bookworm.optional_packages.contains(&"libgl1".to_string()));
+        assert_eq!(bullseye.packages, bookworm.packages);
+        // sid has no VERSION_ID and gets the unbounded rule
+        assert_eq!(get_packages("ID=debian\n"), bookworm);
+
+        let jammy = get_packages("ID=ubuntu\nVERSION_ID=\"22.04\"\n");
+        assert!(jammy
+            .optional_packages
+            .contains(&"libgl1-mesa-glx".to_string()));
+        let noble = get_packages("ID=ubuntu\nVERSION_ID=\"24.04\"\n");
+        assert!(noble.optional_packages.contains(&"libgl1".to_string()));
+
+        let centos7 = get_packages("ID=centos\nVERSION_ID=\"7\"\n");
+        assert!(centos7.optional_packages.contains(&"vulkan".to_string()));
+        let alma9 =
+            get_packages("ID=almalinux\nID_LIKE=\"rhel centos fedora\"\nVERSION_ID=\"9.3\"\n");
+        assert!(alma9
+            .optional_packages
+            .contains(&"vulkan-loader".to_string()));
+        assert_eq!(alma9, get_packages("ID=rocky\nVERSION_ID=\"9.3\"\n"));
     }
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("9.3", "10"), Ordering::Less);
+        assert_eq!(compare_versions("24.04", "24.04"), Ordering::Equal);
+        assert_eq!(compare_versions("12", "12.0"), Ordering::Equal);
+        assert_eq!(compare_versions("2024.1", "12"), Ordering::Greater);
+    }
+
     #[test]
     fn test_build_distrobox_for_alpine() {
-        test_distrobox_packages("alpine", "alpine");
+        test_distrobox_packages("alpine", "ID=alpine\nVERSION_ID=3.18.4\n");
     }
 
     #[test]
     fn test_build_distrobox_for_arch() {
-        test_distrobox_packages("arch", "archlinux");
+        test_distrobox_packages("arch", "ID=arch\n");
     }
 
     #[test]
     fn test_build_distrobox_for_centos() {
-        test_distrobox_packages("centos", "centos:centos7");
+        test_distrobox_packages(
+            "centos",
+            "ID=\"centos\"\nID_LIKE=\"rhel fedora\"\nVERSION_ID=\"7\"\n",
+        );
     }
 
     #[test]
     fn test_build_distrobox_for_debian() {
-        test_distrobox_packages("debian", "debian");
+        test_distrobox_packages("debian", "ID=debian\nVERSION_ID=\"12\"\n");
     }
 
     #[test]
     fn test_build_distrobox_for_fedora() {
-        test_distrobox_packages("fedora", "registry.fedoraproject.org/fedora-toolbox:latest");
+        test_distrobox_packages("fedora", "ID=fedora\nVERSION_ID=38\n");
     }
 
     #[test]
     fn test_build_distrobox_for_opensuse() {
         test_distrobox_packages(
             "opensuse",
-            "registry.opensuse.org/opensuse/distrobox:latest",
+            "ID=\"opensuse-tumbleweed\"\nID_LIKE=\"opensuse suse\"\nVERSION_ID=\"20230901\"\n",
         );
     }
 
     #[test]
     fn test_build_distrobox_for_rocky() {
-        test_distrobox_packages("rocky", "rockylinux:9");
+        test_distrobox_packages(
+            "rocky",
+            "ID=\"rocky\"\nID_LIKE=\"rhel centos fedora\"\nVERSION_ID=\"9.2\"\n",
+        );
     }
 
     #[test]
     fn test_build_distrobox_for_ubuntu() {
-        test_distrobox_packages("ubuntu", "ubuntu");
+        test_distrobox_packages("ubuntu", UBUNTU_OS_RELEASE);
     }
 }
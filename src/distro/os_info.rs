@@ -1,18 +1,74 @@
-pub fn parse_os_release(content: &str) -> Option<(String, String)> {
-    let mut os_id = None;
-    let mut os_version_id = None;
+/// Fields of /etc/os-release used to pick the package manager and requirements
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OsRelease {
+    pub id: String,
+    pub id_like: Vec<String>,
+    pub version_id: Option<String>,
+    pub version_codename: Option<String>,
+    pub pretty_name: Option<String>,
+    pub name: Option<String>,
+}
 
-    for line in content.lines() {
-        if line.starts_with("ID=") {
-            os_id = Some(line[3..].trim_matches('"').to_string());
-        } else if line.starts_with("VERSION_ID=") {
-            os_version_id = Some(line[11..].trim_matches('"').to_string());
+impl OsRelease {
+    /// ID followed by ID_LIKE, from the most to the least specific distro
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.id.as_str()).chain(self.id_like.iter().map(String::as_str))
+    }
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            // shell-like escapes of "$", "`", "\"" and "\\"
+            let mut result = String::new();
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                if c == '\\' {
+                    if let Some(next) = chars.next() {
+                        result.push(next);
+                    }
+                } else {
+                    result.push(c);
+                }
+            }
+            return result;
         }
     }
+    value.to_string()
+}
 
-    match (os_id, os_version_id) {
-        (Some(id), Some(version_id)) => Some((id, version_id)),
-        _ => None,
+/// `None` if there is no ID, every other field is optional
+pub fn parse_os_release(content: &str) -> Option<OsRelease> {
+    let mut os_release = OsRelease::default();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = unquote(value);
+        match key {
+            "ID" => os_release.id = value,
+            "ID_LIKE" => {
+                os_release.id_like = value.split_whitespace().map(str::to_string).collect()
+            }
+            "VERSION_ID" => os_release.version_id = Some(value),
+            "VERSION_CODENAME" => os_release.version_codename = Some(value),
+            "PRETTY_NAME" => os_release.pretty_name = Some(value),
+            "NAME" => os_release.name = Some(value),
+            _ => {}
+        }
+    }
+    if os_release.id.is_empty() {
+        None
+    } else {
+        Some(os_release)
     }
 }
 
@@ -21,10 +77,54 @@ mod tests {
     use super::*;
     use crate::config::get_container_manager;
     use crate::oci::command_helper::run_container;
+    use crate::oci::container_command::ContainerCommand;
+    use crate::oci::fake_runtime::UBUNTU_OS_RELEASE;
+
+    #[test]
+    fn test_parse_os_release_fields() {
+        let os_release = parse_os_release(UBUNTU_OS_RELEASE).unwrap();
+        assert_eq!(
+            os_release,
+            OsRelease {
+                id: "ubuntu".to_string(),
+                id_like: vec!["debian".to_string()],
+                version_id: Some("22.04".to_string()),
+                version_codename: Some("jammy".to_string()),
+                pretty_name: Some("Ubuntu 22.04.3 LTS".to_string()),
+                name: Some("Ubuntu".to_string()),
+            }
+        );
+        assert_eq!(
+            os_release.ids().collect::<Vec<&str>>(),
+            ["ubuntu", "debian"]
+        );
+    }
+
+    #[test]
+    fn test_parse_os_release_without_version() {
+        let content =
+            "NAME=\"Arch Linux\"\nPRETTY_NAME='Arch Linux'\nID=arch\nBUILD_ID=rolling\n# comment\n";
+        let os_release = parse_os_release(content).unwrap();
+        assert_eq!(os_release.id, "arch");
+        assert_eq!(os_release.version_id, None);
+        assert_eq!(os_release.pretty_name.as_deref(), Some("Arch Linux"));
+        assert!(os_release.id_like.is_empty());
+
+        let content = "NAME=\"Linux \\\"Mint\\\"\"\nID=linuxmint\nID_LIKE=\"ubuntu debian\"\n";
+        let os_release = parse_os_release(content).unwrap();
+        assert_eq!(os_release.name.as_deref(), Some("Linux \"Mint\""));
+        assert_eq!(
+            os_release.ids().collect::<Vec<&str>>(),
+            ["linuxmint", "ubuntu", "debian"]
+        );
+
+        assert_eq!(parse_os_release("NAME=nothing\n"), None);
+    }
 
     macro_rules! create_test {
         ($test_name:ident, $image_name:expr) => {
             #[test]
+            #[ignore = "needs a container engine and pulls images"]
             fn $test_name() {
                 test_parse_os_release(&get_container_manager(), $image_name);
             }
@@ -51,7 +151,7 @@ mod tests {
             container_runner,
             "",
             image_name,
-            "cat /etc/os-release",
+            &ContainerCommand::exec(&["cat", "/etc/os-release"]),
             true,
         )
         .unwrap();
@@ -0,0 +1,96 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageChange {
+    pub name: String,
+    /// `None` when the package was added
+    pub old_version: Option<String>,
+    /// `None` when the package was removed
+    pub new_version: Option<String>,
+}
+
+impl fmt::Display for PackageChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.name,
+            self.old_version.as_deref().unwrap_or("(none)"),
+            self.new_version.as_deref().unwrap_or("(none)")
+        )
+    }
+}
+
+/// Packages added, removed or with another version in `after`, sorted by name
+pub fn diff_packages(
+    before: &[(String, String)],
+    after: &[(String, String)],
+) -> Vec<PackageChange> {
+    let before: BTreeMap<&String, &String> = before.iter().map(|(n, v)| (n, v)).collect();
+    let after: BTreeMap<&String, &String> = after.iter().map(|(n, v)| (n, v)).collect();
+    let mut names: Vec<&String> = before.keys().chain(after.keys()).cloned().collect();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .filter(|name| before.get(name) != after.get(name))
+        .map(|name| PackageChange {
+            name: name.clone(),
+            old_version: before.get(name).map(|v| v.to_string()),
+            new_version: after.get(name).map(|v| v.to_string()),
+        })
+        .collect()
+}
+
+/// One line per change: "+ name version", "- name version" or "~ name old -> new"
+pub fn format_package_changes(changes: &[PackageChange]) -> String {
+    changes
+        .iter()
+        .map(|change| match (&change.old_version, &change.new_version) {
+            (None, Some(new)) => format!("+ {} {}\n", change.name, new),
+            (Some(old), None) => format!("- {} {}\n", change.name, old),
+            (old, new) => format!(
+                "~ {} {} -> {}\n",
+                change.name,
+                old.as_deref().unwrap_or_default(),
+                new.as_deref().unwrap_or_default()
+            ),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packages(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_packages() {
+        let before = packages(&[("bash", "5.1"), ("curl", "7.81"), ("vim", "8.2")]);
+        let after = packages(&[("bash", "5.2"), ("curl", "7.81"), ("htop", "3.0")]);
+        let changes = diff_packages(&before, &after);
+        assert_eq!(
+            changes
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>(),
+            [
+                "bash: 5.1 -> 5.2",
+                "htop: (none) -> 3.0",
+                "vim: 8.2 -> (none)"
+            ]
+        );
+        assert!(diff_packages(&before, &before).is_empty());
+        assert_eq!(
+            format_package_changes(&changes),
+            "~ bash 5.1 -> 5.2\n+ htop 3.0\n- vim 8.2\n"
+        );
+    }
+}
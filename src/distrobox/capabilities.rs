@@ -0,0 +1,168 @@
+// what the installed distrobox supports, so the images match the distrobox that runs them
+
+use crate::distro::distrobox_requirements::compare_versions;
+use crate::distrobox::command_helper::distrobox_version;
+use crate::distrobox::parser::config::find_in_path;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Marker distrobox-init has checked since 1.4, used when distrobox is not installed
+pub const DEFAULT_SETUP_MARKER: &str = "/run/.containersetupdone";
+
+/// First distrobox whose distrobox-assemble reads `pull`
+pub const ASSEMBLE_PULL_VERSION: &str = "1.5.0";
+
+// files a distrobox-init has used to skip the setup done in the image, newest first
+const SETUP_MARKERS: &[&str] = &["/run/.containersetupdone", "/.containersetupdone"];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DistroboxCapabilities {
+    /// `None` when `distrobox version` could not be run
+    pub version: Option<String>,
+    pub init_path: Option<PathBuf>,
+    /// Marker file the installed distrobox-init checks, `None` if it has none
+    pub setup_marker: Option<String>,
+}
+
+impl DistroboxCapabilities {
+    /// Marker to create in built images and a warning when the choice is a guess or
+    /// there is nothing distrobox-init would skip
+    pub fn choose_setup_marker(&self) -> (Option<String>, Option<String>) {
+        match (&self.init_path, &self.setup_marker) {
+            (None, _) => (
+                Some(DEFAULT_SETUP_MARKER.to_string()),
+                Some(format!(
+                    "distrobox-init not found, assuming it skips its setup when {} exists",
+                    DEFAULT_SETUP_MARKER
+                )),
+            ),
+            (Some(_), Some(marker)) => (Some(marker.clone()), None),
+            (Some(init_path), None) => (
+                None,
+                Some(format!(
+                    "{} checks no setup marker, containers will do their setup on first start",
+                    init_path.display()
+                )),
+            ),
+        }
+    }
+
+    /// Whether `pull=false` goes into the generated assemble files, so distrobox uses the
+    /// built image instead of pulling it, and a warning when the installed distrobox is
+    /// too old to read `pull`; an unknown version is taken as a current one
+    pub fn choose_assemble_pull(&self) -> (bool, Option<String>) {
+        match &self.version {
+            Some(version) if compare_versions(version, ASSEMBLE_PULL_VERSION).is_lt() => (
+                false,
+                Some(format!(
+                    "distrobox {} does not read pull in assemble files, it needs {} or later, pull is left as it is",
+                    version, ASSEMBLE_PULL_VERSION
+                )),
+            ),
+            _ => (true, None),
+        }
+    }
+}
+
+/// Version from the output of `distrobox version`, e.g. "distrobox: 1.7.2.1"
+pub fn parse_distrobox_version(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("distrobox:"))
+        .map(|version| version.trim().to_string())
+        .filter(|version| !version.is_empty())
+}
+
+/// Setup marker a distrobox-init script checks
+pub fn find_setup_marker(init_script: &str) -> Option<String> {
+    SETUP_MARKERS
+        .iter()
+        .find(|marker| {
+            init_script.match_indices(**marker).any(|(i, _)| {
+                // "/.containersetupdone" is also the end of "/run/.containersetupdone"
+                !init_script[..i].ends_with("/run")
+            })
+        })
+        .map(|marker| marker.to_string())
+}
+
+/// Run `distrobox version` and read the distrobox-init next to distrobox or in PATH
+pub fn probe_distrobox() -> DistroboxCapabilities {
+    let env: HashMap<String, String> = std::env::vars().collect();
+    let version = distrobox_version()
+        .ok()
+        .and_then(|output| parse_distrobox_version(&output.stdout));
+    let init_path = find_in_path(&env, "distrobox")
+        .and_then(|path| Some(path.parent()?.join("distrobox-init")))
+        .filter(|path| path.is_file())
+        .or_else(|| find_in_path(&env, "distrobox-init"));
+    let setup_marker = init_path
+        .as_ref()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|script| find_setup_marker(&script));
+    DistroboxCapabilities {
+        version,
+        init_path,
+        setup_marker,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_distrobox_version() {
+        assert_eq!(
+            parse_distrobox_version("distrobox: 1.7.2.1\n"),
+            Some("1.7.2.1".to_string())
+        );
+        assert_eq!(parse_distrobox_version("command not found"), None);
+    }
+
+    #[test]
+    fn test_choose_setup_marker() {
+        let init = "if [ -e /run/.containersetupdone ]; then\n";
+        assert_eq!(
+            find_setup_marker(init),
+            Some("/run/.containersetupdone".to_string())
+        );
+        assert_eq!(
+            find_setup_marker("touch /.containersetupdone"),
+            Some("/.containersetupdone".to_string())
+        );
+        assert_eq!(find_setup_marker("# nothing to skip"), None);
+
+        let mut capabilities = DistroboxCapabilities::default();
+        let (marker, warning) = capabilities.choose_setup_marker();
+        assert_eq!(marker.as_deref(), Some(DEFAULT_SETUP_MARKER));
+        assert!(warning.is_some());
+
+        capabilities.init_path = Some(PathBuf::from("/usr/bin/distrobox-init"));
+        capabilities.setup_marker = Some("/.containersetupdone".to_string());
+        assert_eq!(
+            capabilities.choose_setup_marker(),
+            (Some("/.containersetupdone".to_string()), None)
+        );
+
+        capabilities.setup_marker = None;
+        let (marker, warning) = capabilities.choose_setup_marker();
+        assert_eq!(marker, None);
+        assert!(warning.unwrap().contains("/usr/bin/distrobox-init"));
+    }
+
+    #[test]
+    fn test_choose_assemble_pull() {
+        let mut capabilities = DistroboxCapabilities::default();
+        assert_eq!(capabilities.choose_assemble_pull(), (true, None));
+
+        capabilities.version = Some("1.7.2.1".to_string());
+        assert_eq!(capabilities.choose_assemble_pull(), (true, None));
+
+        // too old to read pull, the files keep what they had
+        capabilities.version = Some("1.4.2.1".to_string());
+        let (pull, warning) = capabilities.choose_assemble_pull();
+        assert!(!pull);
+        assert!(warning.unwrap().contains("distrobox 1.4.2.1"));
+    }
+}
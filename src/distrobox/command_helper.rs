@@ -17,10 +17,14 @@ pub fn distrobox_enter(
     args: &[&str],
     run_cmds: &[&str],
 ) -> Result<CommandOutput, CommandError> {
-    let mut command_args = vec!["enter","--name", container_name];
+    let mut command_args = vec!["enter", "--name", container_name];
     command_args.extend_from_slice(args);
     command_args.push("--");
     command_args.extend(run_cmds);
     let output = run_command_no_pipe("distrobox", &command_args)?;
     Ok(output)
 }
+
+pub fn distrobox_version() -> Result<CommandOutput, CommandError> {
+    run_command("distrobox", &["version"], false)
+}
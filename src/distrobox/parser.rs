@@ -1,2 +1,3 @@
 pub mod assemble;
 pub mod config;
+pub mod validate;
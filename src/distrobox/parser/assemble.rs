@@ -1,28 +1,157 @@
 use super::config::get_distrobox_config;
-use crate::utils::ini::{from_ini, merge_ini, to_ini};
+use super::validate::{validate_distrobox_assemble_files, Diagnostic};
+use crate::utils::ini::{from_ini, merge_ini, to_ini, IniDocument};
 use std::collections::HashMap;
+use std::fmt;
+
+// keys documented by distrobox-assemble, followed by the ones of the tool
+pub const ASSEMBLE_KEYS: &[&str] = &[
+    "additional_flags",
+    "additional_packages",
+    "home",
+    "image",
+    "clone",
+    "hostname",
+    "init_hooks",
+    "pre_init_hooks",
+    "volume",
+    "volumes",
+    "exported_apps",
+    "exported_bins",
+    "exported_bins_path",
+    "include",
+    "entry",
+    "start_now",
+    "init",
+    "nvidia",
+    "pull",
+    "root",
+    "replace",
+    "unshare_ipc",
+    "unshare_netns",
+    "unshare_process",
+    "unshare_devsys",
+    "unshare_all",
+    "package_manager",
+    "pre_build_cmd",
+    "dockerfile_context",
+    "dockerfile_build_args",
+    "dockerfile_target",
+    "dockerfile_secret",
+];
+
+// keys whose lines add up, an including section adds to the lines of the included one
+pub const ASSEMBLE_LIST_KEYS: &[&str] = &[
+    "additional_flags",
+    "additional_packages",
+    "init_hooks",
+    "pre_init_hooks",
+    "volume",
+    "volumes",
+    "exported_apps",
+    "exported_bins",
+    "pre_build_cmd",
+    "dockerfile_build_args",
+    "dockerfile_secret",
+];
+
+// keys that only take true or false
+pub const ASSEMBLE_BOOL_KEYS: &[&str] = &[
+    "entry",
+    "start_now",
+    "init",
+    "nvidia",
+    "pull",
+    "root",
+    "replace",
+    "unshare_ipc",
+    "unshare_netns",
+    "unshare_process",
+    "unshare_devsys",
+    "unshare_all",
+];
+
+/// One `host:container[:options]` mount of `volume=`
+#[derive(Clone, Debug, PartialEq)]
+pub struct VolumeSpec {
+    pub host: String,
+    /// Empty when only a path was given
+    pub container: String,
+    /// Mount options like `ro` or `z`, comma separated in the spec
+    pub options: Vec<String>,
+}
+
+impl VolumeSpec {
+    pub fn parse(spec: &str) -> Self {
+        let mut split = spec.splitn(3, ':');
+        VolumeSpec {
+            host: split.next().unwrap_or("").to_string(),
+            container: split.next().unwrap_or("").to_string(),
+            options: split
+                .next()
+                .map(|options| {
+                    options
+                        .split(',')
+                        .filter(|option| !option.is_empty())
+                        .map(|option| option.to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
 
-#[derive(Clone)]
+impl fmt::Display for VolumeSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.host)?;
+        if !self.container.is_empty() || !self.options.is_empty() {
+            write!(f, ":{}", self.container)?;
+        }
+        if !self.options.is_empty() {
+            write!(f, ":{}", self.options.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct ContainerAssembleData {
     pub flags: Option<Vec<String>>,
     pub packages: Option<Vec<String>>,
     pub home: Option<String>,
     pub image: String,
+    pub clone: Option<String>,
+    pub hostname: Option<String>,
     pub init_hooks: Option<Vec<String>>,
     pub pre_init_hooks: Option<Vec<String>>,
-    pub volumes: Option<HashMap<String, String>>,
+    /// In file order, from `volume=` or the older `volumes=`, several per line
+    pub volumes: Option<Vec<VolumeSpec>>,
+    pub exported_apps: Option<Vec<String>>,
+    pub exported_bins: Option<Vec<String>>,
+    pub exported_bins_path: Option<String>,
     pub entry: Option<bool>,
     pub start_now: Option<bool>,
     pub init: Option<bool>,
     pub nvidia: Option<bool>,
     pub pull: Option<bool>,
     pub root: Option<bool>,
+    pub replace: Option<bool>,
     pub unshare_ipc: Option<bool>,
     pub unshare_netns: Option<bool>,
+    pub unshare_process: Option<bool>,
+    pub unshare_devsys: Option<bool>,
+    pub unshare_all: Option<bool>,
 
     // extra fields for the tool
     pub package_manager: Option<String>,
     pub pre_build_cmd: Option<String>,
+    pub dockerfile_context: Option<String>,
+    pub dockerfile_build_args: Option<Vec<String>>,
+    pub dockerfile_target: Option<String>,
+    pub dockerfile_secret: Option<Vec<String>>,
+
+    /// Keys neither distrobox-assemble nor the tool know, written back as they were read
+    pub unknown_keys: Vec<(String, String)>,
 }
 impl Default for ContainerAssembleData {
     fn default() -> Self {
@@ -35,40 +164,85 @@ impl Default for ContainerAssembleData {
                 .get("container_image_default")
                 .expect("No default image found")
                 .clone(),
+            clone: None,
+            hostname: None,
             init_hooks: None,
             pre_init_hooks: None,
             volumes: None,
+            exported_apps: None,
+            exported_bins: None,
+            exported_bins_path: None,
             entry: None,
             start_now: None,
             init: None,
             nvidia: None,
             pull: None,
             root: None,
+            replace: None,
             unshare_ipc: None,
             unshare_netns: None,
+            unshare_process: None,
+            unshare_devsys: None,
+            unshare_all: None,
             package_manager: None,
             pre_build_cmd: None,
+            dockerfile_context: None,
+            dockerfile_build_args: None,
+            dockerfile_target: None,
+            dockerfile_secret: None,
+            unknown_keys: vec![],
         }
     }
 }
 
-pub fn parse_distrobox_assemble(content: &str) -> HashMap<String, ContainerAssembleData> {
-    let parsed = from_ini(content);
-    let merged = merge_ini(parsed);
+/// Containers of an assemble file, or its errors when it does not pass validation
+pub fn parse_distrobox_assemble(
+    content: &str,
+) -> Result<HashMap<String, ContainerAssembleData>, Vec<Diagnostic>> {
+    let errors: Vec<Diagnostic> = validate_distrobox_assemble_files(&[("", content)])
+        .into_iter()
+        .filter(|diagnostic| diagnostic.is_error())
+        .collect();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
 
-    merged
+    // `volumes` is the older name of `volume`, one key keeps the lines in file order
+    let parsed = from_ini(content)
         .into_iter()
-        .map(|(name, entry)| {
+        .map(|(section, entries)| {
+            let entries = entries
+                .into_iter()
+                .map(|(key, value)| match key.as_str() {
+                    "volumes" => ("volume".to_string(), value),
+                    _ => (key, value),
+                })
+                .collect();
+            (section, entries)
+        })
+        .collect();
+    let merged = merge_ini(parsed);
+    let document = IniDocument::parse(content);
+    let templates = get_included_sections(&merged);
+
+    Ok(merged
+        .keys()
+        .filter(|name| !templates.contains(*name))
+        .map(|name| {
+            let chain = get_include_chain(name, &merged);
+            let entry = merge_included_entries(&chain, &merged);
+            let unknown_keys = chain
+                .iter()
+                .flat_map(|section| document.raw_entries(section))
+                .filter(|(key, _)| !ASSEMBLE_KEYS.contains(&key.as_str()))
+                .collect();
+            let volumes = get_words(&entry, "volume")
+                .map(|specs| specs.iter().map(|spec| VolumeSpec::parse(spec)).collect());
             (
-                name,
+                name.clone(),
                 ContainerAssembleData {
-                    flags: entry.get("additional_flags").map(|i| i.clone()),
-                    packages: entry.get("additional_packages").map(|i| {
-                        i.iter()
-                            .flat_map(|pkg_str| pkg_str.split_whitespace())
-                            .map(|pkg| pkg.to_string())
-                            .collect::<Vec<String>>()
-                    }),
+                    flags: entry.get("additional_flags").cloned(),
+                    packages: get_words(&entry, "additional_packages"),
                     home: entry.get("home").map(|h| h.join(" ")),
                     image: entry
                         .get("image")
@@ -76,38 +250,46 @@ pub fn parse_distrobox_assemble(content: &str) -> HashMap<String, ContainerAssem
                         .last()
                         .unwrap()
                         .to_string(),
-                    init_hooks: entry.get("init_hooks").map(|i| i.clone()),
-                    pre_init_hooks: entry.get("pre_init_hooks").map(|i| i.clone()),
-                    volumes: entry.get("volumes").map(|i| {
-                        i.iter()
-                            .map(|volume_str| {
-                                let mut split = volume_str.split(':');
-                                let key = split.next().unwrap_or("").to_string();
-                                let value = split.next().unwrap_or("").to_string();
-                                (key, value)
-                            })
-                            .collect()
-                    }),
+                    clone: get_last_value(&entry, "clone"),
+                    hostname: get_last_value(&entry, "hostname"),
+                    init_hooks: entry.get("init_hooks").cloned(),
+                    pre_init_hooks: entry.get("pre_init_hooks").cloned(),
+                    volumes,
+                    exported_apps: get_words(&entry, "exported_apps"),
+                    exported_bins: get_words(&entry, "exported_bins"),
+                    exported_bins_path: get_last_value(&entry, "exported_bins_path"),
                     entry: get_value_as_bool_with_default(&entry, "entry"),
                     start_now: get_value_as_bool_with_default(&entry, "start_now"),
                     init: get_value_as_bool_with_default(&entry, "init"),
                     nvidia: get_value_as_bool_with_default(&entry, "nvidia"),
                     pull: get_value_as_bool_with_default(&entry, "pull"),
                     root: get_value_as_bool_with_default(&entry, "root"),
+                    replace: get_value_as_bool_with_default(&entry, "replace"),
                     unshare_ipc: get_value_as_bool_with_default(&entry, "unshare_ipc"),
                     unshare_netns: get_value_as_bool_with_default(&entry, "unshare_netns"),
+                    unshare_process: get_value_as_bool_with_default(&entry, "unshare_process"),
+                    unshare_devsys: get_value_as_bool_with_default(&entry, "unshare_devsys"),
+                    unshare_all: get_value_as_bool_with_default(&entry, "unshare_all"),
                     package_manager: entry.get("package_manager").map(|h| h.join(" ")),
                     pre_build_cmd: entry.get("pre_build_cmd").map(|h| h.join(";")),
+                    dockerfile_context: entry.get("dockerfile_context").map(|h| h.join(" ")),
+                    dockerfile_build_args: entry.get("dockerfile_build_args").cloned(),
+                    dockerfile_target: entry.get("dockerfile_target").map(|h| h.join(" ")),
+                    dockerfile_secret: entry.get("dockerfile_secret").cloned(),
+                    unknown_keys,
                 },
             )
         })
-        .collect()
+        .collect())
 }
 
 pub fn assemble_distrobox_to_str(data: &HashMap<String, ContainerAssembleData>) -> String {
     let mut ini_data = Vec::new();
 
-    for (name, assemble_data) in data {
+    let mut names: Vec<&String> = data.keys().collect();
+    names.sort();
+    for name in names {
+        let assemble_data = &data[name];
         let mut single_ini_data = Vec::new();
         if let Some(flags) = &assemble_data.flags {
             single_ini_data.push(("additional_flags".to_string(), flags.join(" ")));
@@ -121,6 +303,12 @@ pub fn assemble_distrobox_to_str(data: &HashMap<String, ContainerAssembleData>)
             single_ini_data.push(("home".to_string(), home.clone()));
         }
         single_ini_data.push(("image".to_string(), assemble_data.image.clone()));
+        if let Some(clone) = &assemble_data.clone {
+            single_ini_data.push(("clone".to_string(), clone.clone()));
+        }
+        if let Some(hostname) = &assemble_data.hostname {
+            single_ini_data.push(("hostname".to_string(), hostname.clone()));
+        }
         if let Some(init_hooks) = &assemble_data.init_hooks {
             for init_hook in init_hooks {
                 single_ini_data.push(("init_hooks".to_string(), init_hook.clone()));
@@ -134,49 +322,221 @@ pub fn assemble_distrobox_to_str(data: &HashMap<String, ContainerAssembleData>)
         }
 
         if let Some(volumes) = &assemble_data.volumes {
-            let volume_str = volumes
-                .iter()
-                .map(|(k, v)| format!("{}:{}", k, v))
-                .collect::<Vec<_>>()
-                .join(" ");
-            single_ini_data.push(("volumes".to_string(), volume_str));
+            if !volumes.is_empty() {
+                let volume_str = volumes
+                    .iter()
+                    .map(|volume| volume.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                single_ini_data.push(("volume".to_string(), volume_str));
+            }
         }
-        if let Some(entry) = assemble_data.entry {
-            single_ini_data.push(("entry".to_string(), entry.to_string()));
+        if let Some(exported_apps) = &assemble_data.exported_apps {
+            if !exported_apps.is_empty() {
+                single_ini_data.push(("exported_apps".to_string(), exported_apps.join(" ")));
+            }
         }
-        if let Some(start_now) = assemble_data.start_now {
-            single_ini_data.push(("start_now".to_string(), start_now.to_string()));
+        if let Some(exported_bins) = &assemble_data.exported_bins {
+            if !exported_bins.is_empty() {
+                single_ini_data.push(("exported_bins".to_string(), exported_bins.join(" ")));
+            }
         }
-        if let Some(init) = assemble_data.init {
-            single_ini_data.push(("init".to_string(), init.to_string()));
+        if let Some(exported_bins_path) = &assemble_data.exported_bins_path {
+            single_ini_data.push(("exported_bins_path".to_string(), exported_bins_path.clone()));
         }
-        if let Some(nvidia) = assemble_data.nvidia {
-            single_ini_data.push(("nvidia".to_string(), nvidia.to_string()));
+        let bool_keys = [
+            ("entry", assemble_data.entry),
+            ("start_now", assemble_data.start_now),
+            ("init", assemble_data.init),
+            ("nvidia", assemble_data.nvidia),
+            ("pull", assemble_data.pull),
+            ("root", assemble_data.root),
+            ("replace", assemble_data.replace),
+            ("unshare_ipc", assemble_data.unshare_ipc),
+            ("unshare_netns", assemble_data.unshare_netns),
+            ("unshare_process", assemble_data.unshare_process),
+            ("unshare_devsys", assemble_data.unshare_devsys),
+            ("unshare_all", assemble_data.unshare_all),
+        ];
+        for (key, value) in bool_keys {
+            if let Some(value) = value {
+                single_ini_data.push((key.to_string(), value.to_string()));
+            }
         }
-        if let Some(pull) = assemble_data.pull {
-            single_ini_data.push(("pull".to_string(), pull.to_string()));
+        if let Some(package_manager) = &assemble_data.package_manager {
+            single_ini_data.push(("package_manager".to_string(), package_manager.clone()));
         }
-        if let Some(root) = assemble_data.root {
-            single_ini_data.push(("root".to_string(), root.to_string()));
+        if let Some(pre_build_cmd) = &assemble_data.pre_build_cmd {
+            single_ini_data.push(("pre_build_cmd".to_string(), pre_build_cmd.clone()));
         }
-        if let Some(unshare_ipc) = assemble_data.unshare_ipc {
-            single_ini_data.push(("unshare_ipc".to_string(), unshare_ipc.to_string()));
+        if let Some(context) = &assemble_data.dockerfile_context {
+            single_ini_data.push(("dockerfile_context".to_string(), context.clone()));
         }
-        if let Some(unshare_netns) = assemble_data.unshare_netns {
-            single_ini_data.push(("unshare_netns".to_string(), unshare_netns.to_string()));
+        if let Some(build_args) = &assemble_data.dockerfile_build_args {
+            for build_arg in build_args {
+                single_ini_data.push(("dockerfile_build_args".to_string(), build_arg.clone()));
+            }
         }
-        if let Some(package_manager) = &assemble_data.package_manager {
-            single_ini_data.push(("package_manager".to_string(), package_manager.clone()));
+        if let Some(target) = &assemble_data.dockerfile_target {
+            single_ini_data.push(("dockerfile_target".to_string(), target.clone()));
         }
-        if let Some(pre_build_image) = &assemble_data.pre_build_cmd {
-            single_ini_data.push(("pre_build_image".to_string(), pre_build_image.clone()));
+        if let Some(secrets) = &assemble_data.dockerfile_secret {
+            for secret in secrets {
+                single_ini_data.push(("dockerfile_secret".to_string(), secret.clone()));
+            }
         }
+        single_ini_data.extend(assemble_data.unknown_keys.iter().cloned());
 
         ini_data.push((name.clone(), single_ini_data));
     }
     to_ini(&ini_data)
 }
 
+/// Write the `image`, `pull` and `additional_packages` of `data` into a parsed assemble
+/// file, only the lines whose value changed are touched and missing containers are appended
+pub fn update_distrobox_assemble_document(
+    document: &mut IniDocument,
+    data: &HashMap<String, ContainerAssembleData>,
+) {
+    // the containers as the file defines them, with what they include
+    let included = parse_distrobox_assemble(&document.to_string()).unwrap_or_default();
+    let mut names: Vec<&String> = data.keys().collect();
+    names.sort();
+    for name in names {
+        let assemble_data = &data[name];
+        if !document.has_section(name) {
+            let single_data = HashMap::from([(name.clone(), assemble_data.clone())]);
+            document.append(IniDocument::parse(&assemble_distrobox_to_str(&single_data)));
+            continue;
+        }
+
+        if document.get_values(name, "image").last() != Some(&assemble_data.image) {
+            document.set_values(name, "image", std::slice::from_ref(&assemble_data.image));
+        }
+        if let Some(pull) = assemble_data.pull {
+            let current = document
+                .get_values(name, "pull")
+                .first()
+                .and_then(|value| value.parse::<bool>().ok());
+            if current != Some(pull) {
+                document.set_values(name, "pull", &[pull.to_string()]);
+            }
+        }
+        let packages = assemble_data.packages.clone().unwrap_or_default();
+        let own: Vec<String> = document
+            .get_values(name, "additional_packages")
+            .iter()
+            .flat_map(|value| value.split_whitespace())
+            .map(|package| package.to_string())
+            .collect();
+        let current = included
+            .get(name)
+            .and_then(|data| data.packages.clone())
+            .unwrap_or(own.clone());
+        if current != packages {
+            // packages of included sections stay there
+            let inherited: Vec<&String> = current
+                .iter()
+                .filter(|package| !own.contains(package))
+                .collect();
+            let packages: Vec<String> = packages
+                .into_iter()
+                .filter(|package| !inherited.contains(&package))
+                .collect();
+            let values = if packages.is_empty() {
+                vec![]
+            } else {
+                vec![packages.join(" ")]
+            };
+            document.set_values(name, "additional_packages", &values);
+        }
+    }
+}
+
+/// Sections named by an `include=`, they are templates and not containers
+fn get_included_sections(merged: &HashMap<String, HashMap<String, Vec<String>>>) -> Vec<String> {
+    let mut templates: Vec<String> = merged
+        .values()
+        .filter_map(|entry| entry.get("include"))
+        .flatten()
+        .map(|include| include.trim().to_string())
+        .collect();
+    templates.sort();
+    templates.dedup();
+    templates
+}
+
+/// `name` after the sections it includes, includes of includes first
+fn get_include_chain(
+    name: &str,
+    merged: &HashMap<String, HashMap<String, Vec<String>>>,
+) -> Vec<String> {
+    fn visit(
+        name: &str,
+        merged: &HashMap<String, HashMap<String, Vec<String>>>,
+        seen: &mut Vec<String>,
+        chain: &mut Vec<String>,
+    ) {
+        // validation rejects cycles, this only keeps a bad file from recursing forever
+        if seen.iter().any(|section| section == name) {
+            return;
+        }
+        seen.push(name.to_string());
+        if let Some(includes) = merged.get(name).and_then(|entry| entry.get("include")) {
+            for include in includes {
+                visit(include.trim(), merged, seen, chain);
+            }
+        }
+        if merged.contains_key(name) {
+            chain.push(name.to_string());
+        }
+    }
+
+    let mut chain = vec![];
+    visit(name, merged, &mut vec![], &mut chain);
+    chain
+}
+
+/// Keys of the sections of `chain` in order, list keys are appended and the other
+/// keys are replaced by the later sections
+fn merge_included_entries(
+    chain: &[String],
+    merged: &HashMap<String, HashMap<String, Vec<String>>>,
+) -> HashMap<String, Vec<String>> {
+    let mut result: HashMap<String, Vec<String>> = HashMap::new();
+    for section in chain {
+        for (key, values) in &merged[section] {
+            if key == "include" {
+                continue;
+            }
+            if ASSEMBLE_LIST_KEYS.contains(&key.as_str()) {
+                result
+                    .entry(key.clone())
+                    .or_default()
+                    .extend(values.clone());
+            } else {
+                result.insert(key.clone(), values.clone());
+            }
+        }
+    }
+    result
+}
+
+/// Whitespace separated values of every `key` line, like `additional_packages`
+fn get_words(map: &HashMap<String, Vec<String>>, key: &str) -> Option<Vec<String>> {
+    map.get(key).map(|values| {
+        values
+            .iter()
+            .flat_map(|value| value.split_whitespace())
+            .map(|word| word.to_string())
+            .collect()
+    })
+}
+
+fn get_last_value(map: &HashMap<String, Vec<String>>, key: &str) -> Option<String> {
+    map.get(key).and_then(|values| values.last().cloned())
+}
+
 fn get_value_as_bool_with_default(map: &HashMap<String, Vec<String>>, key: &str) -> Option<bool> {
     map.get(key)
         .and_then(|value| value.first()?.parse::<bool>().ok())
@@ -210,7 +570,7 @@ unshare_ipc=true
 unshare_netns=false
 "#;
 
-        let result = parse_distrobox_assemble(content);
+        let result = parse_distrobox_assemble(content).unwrap();
 
         assert_eq!(result.len(), 1);
         assert!(result.contains_key("test_section"));
@@ -226,22 +586,214 @@ unshare_netns=false
             &["pre_hook1", "pre_hook2"]
         );
 
-        let expected_volumes: HashMap<String, String> = vec![
-            ("volume1".to_string(), "/mnt/volume1".to_string()),
-            ("volume2".to_string(), "/mnt/volume2".to_string()),
-        ]
-        .into_iter()
-        .collect();
-        assert_eq!(entry.volumes.as_ref().unwrap(), &expected_volumes);
-
-        assert_eq!(entry.entry.unwrap(), true);
-        assert_eq!(entry.start_now.unwrap(), false);
-        assert_eq!(entry.init.unwrap(), true);
-        assert_eq!(entry.nvidia.unwrap(), false);
-        assert_eq!(entry.pull.unwrap(), true);
-        assert_eq!(entry.root.unwrap(), true);
-        assert_eq!(entry.unshare_ipc.unwrap(), true);
-        assert_eq!(entry.unshare_netns.unwrap(), false);
+        assert_eq!(
+            entry.volumes.as_ref().unwrap(),
+            &[
+                VolumeSpec::parse("volume1:/mnt/volume1"),
+                VolumeSpec::parse("volume2:/mnt/volume2")
+            ]
+        );
+
+        assert!(entry.entry.unwrap());
+        assert!(!entry.start_now.unwrap());
+        assert!(entry.init.unwrap());
+        assert!(!entry.nvidia.unwrap());
+        assert!(entry.pull.unwrap());
+        assert!(entry.root.unwrap());
+        assert!(entry.unshare_ipc.unwrap());
+        assert!(!entry.unshare_netns.unwrap());
+    }
+
+    #[test]
+    fn test_parse_distrobox_assemble_dockerfile_options() {
+        let content = r#"
+[test_section]
+image=dockerfile:///src/app/Containerfile
+dockerfile_context=/src
+dockerfile_build_args=VERSION=1.2
+dockerfile_build_args="NAME=my app"
+dockerfile_target=runtime
+dockerfile_secret=id=token,src=/run/secrets/token
+"#;
+
+        let result = parse_distrobox_assemble(content).unwrap();
+        let entry = &result["test_section"];
+        assert_eq!(entry.dockerfile_context.as_deref(), Some("/src"));
+        assert_eq!(
+            entry.dockerfile_build_args.as_ref().unwrap(),
+            &["VERSION=1.2", "NAME=my app"]
+        );
+        assert_eq!(entry.dockerfile_target.as_deref(), Some("runtime"));
+        assert_eq!(
+            entry.dockerfile_secret.as_ref().unwrap(),
+            &["id=token,src=/run/secrets/token"]
+        );
+
+        let output = assemble_distrobox_to_str(&result);
+        assert!(output.contains("dockerfile_build_args=VERSION=1.2\n"));
+        assert!(output.contains("dockerfile_target=runtime\n"));
+    }
+
+    #[test]
+    fn test_parse_distrobox_assemble_all_keys() {
+        let content = std::fs::read_to_string("tests/files/example.ini").unwrap()
+            + r#"
+[full]
+image=fedora:39
+clone=ubuntu
+hostname=devbox
+init_hooks="touch /b"
+init_hooks="touch /a"
+exported_apps="htop code"
+exported_bins=/usr/bin/rg
+exported_bins=/usr/bin/fd
+exported_bins_path=~/.local/bin
+unshare_process=true
+unshare_devsys=false
+unshare_all=false
+pre_build_cmd=echo hi
+future_key="kept  as is" # comment
+"#;
+
+        let result = parse_distrobox_assemble(&content).unwrap();
+        let arch = &result["arch"];
+        let volumes: Vec<String> = arch
+            .volumes
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|volume| volume.to_string())
+            .collect();
+        assert_eq!(volumes, vec!["/tmp/test:/run/a", "/tmp/test:/run/b"]);
+        assert_eq!(arch.replace, Some(false));
+        assert_eq!(result["ubuntu"].replace, Some(true));
+
+        let full = &result["full"];
+        assert_eq!(full.clone.as_deref(), Some("ubuntu"));
+        assert_eq!(full.hostname.as_deref(), Some("devbox"));
+        assert_eq!(full.init_hooks.as_ref().unwrap(), &["touch /b", "touch /a"]);
+        assert_eq!(full.exported_apps.as_ref().unwrap(), &["htop", "code"]);
+        assert_eq!(
+            full.exported_bins.as_ref().unwrap(),
+            &["/usr/bin/rg", "/usr/bin/fd"]
+        );
+        assert_eq!(full.exported_bins_path.as_deref(), Some("~/.local/bin"));
+        assert_eq!(full.unshare_process, Some(true));
+        assert_eq!(full.unshare_devsys, Some(false));
+        assert_eq!(
+            full.unknown_keys,
+            vec![("future_key".to_string(), "\"kept  as is\"".to_string())]
+        );
+
+        let output = assemble_distrobox_to_str(&result);
+        assert!(output.contains("volume=/tmp/test:/run/a /tmp/test:/run/b\n"));
+        assert!(output.contains("pre_build_cmd=echo hi\n"));
+        assert!(output.contains("future_key=\"kept  as is\"\n"));
+        assert_eq!(parse_distrobox_assemble(&output).unwrap(), result);
+    }
+
+    #[test]
+    fn test_volume_spec() {
+        let volume = VolumeSpec::parse("/srv/data:/data:ro,z");
+        assert_eq!(volume.host, "/srv/data");
+        assert_eq!(volume.container, "/data");
+        assert_eq!(volume.options, vec!["ro", "z"]);
+        for spec in ["/srv/data:/data:ro,z", "/a:/b", "/a", "/a::ro"] {
+            assert_eq!(VolumeSpec::parse(spec).to_string(), spec);
+        }
+
+        let content = "\
+[box]
+volume=/a:/b:ro /a:/c
+volume=/d:/e:z
+volumes=/f:/g
+";
+        let result = parse_distrobox_assemble(content).unwrap();
+        let volumes = result["box"].volumes.as_ref().unwrap();
+        assert_eq!(volumes.len(), 4);
+        assert_eq!(volumes[0].options, vec!["ro"]);
+        assert_eq!(volumes[1].container, "/c");
+        assert_eq!(volumes[3].host, "/f");
+
+        let output = assemble_distrobox_to_str(&result);
+        assert!(output.contains("volume=/a:/b:ro /a:/c /d:/e:z /f:/g\n"));
+        assert_eq!(parse_distrobox_assemble(&output).unwrap(), result);
+    }
+
+    #[test]
+    fn test_parse_distrobox_assemble_mixed_volume_keys() {
+        let content = "\
+[box]
+volumes=/a:/b
+volume=/c:/d
+volumes=/e:/f /g:/h
+volume=/i:/j
+";
+        let result = parse_distrobox_assemble(content).unwrap();
+        let containers: Vec<&str> = result["box"]
+            .volumes
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|volume| volume.container.as_str())
+            .collect();
+        assert_eq!(containers, ["/b", "/d", "/f", "/h", "/j"]);
+    }
+
+    #[test]
+    fn test_parse_distrobox_assemble_include() {
+        let content = "\
+[dev-base]
+image=fedora:39
+additional_packages=git vim
+init_hooks=echo base
+nvidia=true
+custom=base
+
+[rust-base]
+include=dev-base
+additional_packages=cargo
+
+[rust]
+include=rust-base
+additional_packages=rust-analyzer
+init_hooks=echo rust
+nvidia=false
+
+[web]
+include=dev-base
+image=ubuntu:22.04
+";
+        let result = parse_distrobox_assemble(content).unwrap();
+        let mut names: Vec<&String> = result.keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["rust", "web"]);
+
+        let rust = &result["rust"];
+        assert_eq!(rust.image, "fedora:39");
+        assert_eq!(
+            rust.packages.as_ref().unwrap(),
+            &["git", "vim", "cargo", "rust-analyzer"]
+        );
+        assert_eq!(
+            rust.init_hooks.as_ref().unwrap(),
+            &["echo base", "echo rust"]
+        );
+        assert_eq!(rust.nvidia, Some(false));
+        assert_eq!(
+            rust.unknown_keys,
+            vec![("custom".to_string(), "base".to_string())]
+        );
+
+        let web = &result["web"];
+        assert_eq!(web.image, "ubuntu:22.04");
+        assert_eq!(web.packages.as_ref().unwrap(), &["git", "vim"]);
+        assert_eq!(web.nvidia, Some(true));
+
+        let output = assemble_distrobox_to_str(&result);
+        assert!(!output.contains("include="));
+        assert!(!output.contains("[dev-base]"));
+        assert_eq!(parse_distrobox_assemble(&output).unwrap(), result);
     }
 
     #[test]
@@ -258,7 +810,7 @@ home=/home/user2
 image=docker.io/library/debian:10
 "#;
 
-        let result = parse_distrobox_assemble(content);
+        let result = parse_distrobox_assemble(content).unwrap();
 
         assert_eq!(result.len(), 2);
         assert!(result.contains_key("section1"));
@@ -285,7 +837,7 @@ additional_packages="nano wget"
 home=/home/test_user
 "#;
 
-        let result = parse_distrobox_assemble(content);
+        let result = parse_distrobox_assemble(content).unwrap();
 
         assert_eq!(result.len(), 1);
         assert!(result.contains_key("test_section"));
@@ -304,7 +856,7 @@ home=/home/test_user
     fn test_parse_distrobox_assemble_empty_input() {
         let content = "";
 
-        let result = parse_distrobox_assemble(content);
+        let result = parse_distrobox_assemble(content).unwrap();
 
         assert_eq!(result.len(), 0);
     }
@@ -313,7 +865,7 @@ home=/home/test_user
     fn test_parse_distrobox_assemble_whitespace_only_input() {
         let content = "       \n   \t   ";
 
-        let result = parse_distrobox_assemble(content);
+        let result = parse_distrobox_assemble(content).unwrap();
 
         assert_eq!(result.len(), 0);
     }
@@ -332,9 +884,11 @@ home=/home/test_user
     fn test_assemble_distrobox_to_str_with_fields() {
         let mut data = HashMap::new();
 
-        let mut foo = ContainerAssembleData::default();
-        foo.flags = Some(vec!["--net".to_string()]);
-        foo.packages = Some(vec!["vim".to_string()]);
+        let foo = ContainerAssembleData {
+            flags: Some(vec!["--net".to_string()]),
+            packages: Some(vec!["vim".to_string()]),
+            ..Default::default()
+        };
 
         data.insert("foo".to_string(), foo);
 
@@ -361,11 +915,13 @@ home=/home/test_user
     fn test_assemble_distrobox_to_str_with_pre_init_hooks() {
         let mut data = HashMap::new();
 
-        let mut foo = ContainerAssembleData::default();
-        foo.pre_init_hooks = Some(vec![
-            r#"do something 'funny' here"#.to_string(),
-            r#"echo 'haha'"#.to_string(),
-        ]);
+        let foo = ContainerAssembleData {
+            pre_init_hooks: Some(vec![
+                r#"do something 'funny' here"#.to_string(),
+                r#"echo 'haha'"#.to_string(),
+            ]),
+            ..Default::default()
+        };
 
         data.insert("foo".to_string(), foo);
 
@@ -384,4 +940,77 @@ home=/home/test_user
 
         assert_eq!(result, "");
     }
+
+    #[test]
+    fn test_update_distrobox_assemble_document() {
+        let content = "\
+# hand written
+[dev]
+image = \"ubuntu:22.04\"  # base
+additional_packages=git
+additional_packages=vim ; editor
+init_hooks=echo 'hi'
+
+[tools]
+pull=false
+image=alpine
+";
+        let mut document = IniDocument::parse(content);
+        let mut data = parse_distrobox_assemble(content).unwrap();
+        data.get_mut("dev").unwrap().image = "distrobox-boost/release/dev".to_string();
+        data.get_mut("dev").unwrap().pull = Some(false);
+        data.get_mut("tools").unwrap().pull = Some(false);
+        data.insert(
+            "extra".to_string(),
+            ContainerAssembleData {
+                image: "fedora".to_string(),
+                ..Default::default()
+            },
+        );
+
+        update_distrobox_assemble_document(&mut document, &data);
+        assert_eq!(
+            document.to_string(),
+            "\
+# hand written
+[dev]
+image = \"distrobox-boost/release/dev\"  # base
+additional_packages=git
+additional_packages=vim ; editor
+init_hooks=echo 'hi'
+pull=false
+
+[tools]
+pull=false
+image=alpine
+
+[extra]
+image=fedora
+
+"
+        );
+
+        data.get_mut("dev").unwrap().packages = Some(vec!["git".to_string(), "fish".to_string()]);
+        update_distrobox_assemble_document(&mut document, &data);
+        assert_eq!(
+            document.get_values("dev", "additional_packages"),
+            vec!["git fish"]
+        );
+
+        let content = "[base]\nadditional_packages=git\n\n[app]\ninclude=base\nimage=fedora\n";
+        let mut document = IniDocument::parse(content);
+        let mut data = parse_distrobox_assemble(content).unwrap();
+        update_distrobox_assemble_document(&mut document, &data);
+        assert_eq!(document.to_string(), content);
+        data.get_mut("app").unwrap().packages = Some(vec!["git".to_string(), "fish".to_string()]);
+        update_distrobox_assemble_document(&mut document, &data);
+        assert_eq!(
+            document.get_values("app", "additional_packages"),
+            vec!["fish"]
+        );
+        assert_eq!(
+            document.get_values("base", "additional_packages"),
+            vec!["git"]
+        );
+    }
 }
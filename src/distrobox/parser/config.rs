@@ -1,125 +1,293 @@
+// distrobox settings as distrobox-create sees them: its defaults, the distrobox.conf
+// files and the DBX_* environment variables, read once without running a shell
+
+use lazy_static::lazy_static;
 use std::collections::HashMap;
-use std::process::Command;
-
-fn extract_environment_variables(
-    script: &str,
-) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(script.to_string() + "\nenv")
-        .output()?;
-
-    if !output.status.success() {
-        return Err("Script exited with non-zero status".into());
+use std::path::PathBuf;
+
+// defaults of distrobox-create 1.5.0.2, for what the installed script does not tell
+const DEFAULTS: &[(&str, &str)] = &[
+    ("container_additional_packages", ""),
+    ("container_always_pull", "0"),
+    ("container_clone", ""),
+    ("container_generate_entry", "1"),
+    ("container_home_prefix", ""),
+    ("container_image", ""),
+    (
+        "container_image_default",
+        "registry.fedoraproject.org/fedora-toolbox:38",
+    ),
+    ("container_init_hook", ""),
+    ("container_manager", "autodetect"),
+    ("container_manager_additional_flags", ""),
+    ("container_name", ""),
+    ("container_name_default", "my-distrobox"),
+    ("container_pre_init_hook", ""),
+    ("container_user_custom_home", ""),
+    ("dryrun", "0"),
+    ("init", "0"),
+    ("non_interactive", "0"),
+    ("nvidia", "0"),
+    ("unshare_ipc", "0"),
+    ("unshare_netns", "0"),
+    ("verbose", "0"),
+    ("version", "1.5.0.2"),
+];
+
+// environment variables that replace a setting when they are not empty
+const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("DBX_CONTAINER_ALWAYS_PULL", "container_always_pull"),
+    ("DBX_CONTAINER_CUSTOM_HOME", "container_user_custom_home"),
+    ("DBX_CONTAINER_HOME_PREFIX", "container_home_prefix"),
+    ("DBX_CONTAINER_IMAGE", "container_image"),
+    ("DBX_CONTAINER_MANAGER", "container_manager"),
+    ("DBX_CONTAINER_NAME", "container_name"),
+    ("DBX_NON_INTERACTIVE", "non_interactive"),
+    ("DBX_container_generate_entry", "container_generate_entry"),
+];
+
+lazy_static! {
+    static ref DISTROBOX_CONFIG: HashMap<String, String> = {
+        let env: HashMap<String, String> = std::env::vars().collect();
+        let create_script = find_in_path(&env, "distrobox-create")
+            .and_then(|path| std::fs::read_to_string(path).ok());
+        load_distrobox_config(
+            &get_config_files(&env),
+            &env,
+            create_script.as_deref(),
+            get_user_ids(),
+        )
+    };
+}
+
+/// The settings, computed the first time they are asked for
+pub fn get_distrobox_config() -> &'static HashMap<String, String> {
+    &DISTROBOX_CONFIG
+}
+
+/// Config files in the order distrobox-create sources them, later ones win
+fn get_config_files(env: &HashMap<String, String>) -> Vec<PathBuf> {
+    let home = env.get("HOME").cloned().unwrap_or_default();
+    let config_home = match env.get("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => dir.clone(),
+        _ => format!("{}/.config", home),
+    };
+    vec![
+        PathBuf::from("/usr/share/distrobox/distrobox.conf"),
+        PathBuf::from("/usr/share/defaults/distrobox/distrobox.conf"),
+        PathBuf::from("/usr/etc/distrobox/distrobox.conf"),
+        PathBuf::from("/usr/local/share/distrobox/distrobox.conf"),
+        PathBuf::from("/etc/distrobox/distrobox.conf"),
+        PathBuf::from(config_home).join("distrobox/distrobox.conf"),
+        PathBuf::from(home).join(".distroboxrc"),
+    ]
+}
+
+pub fn find_in_path(env: &HashMap<String, String>, command: &str) -> Option<PathBuf> {
+    env.get("PATH")?
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join(command))
+        .find(|path| path.is_file())
+}
+
+/// Real uid and gid of the process, like `id -ru` and `id -rg`
+fn get_user_ids() -> (String, String) {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+    let get_id = |field: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(field))
+            .and_then(|ids| ids.split_whitespace().next())
+            .unwrap_or("")
+            .to_string()
+    };
+    (get_id("Uid:"), get_id("Gid:"))
+}
+
+/// Settings of distrobox-create: its defaults, taken from `create_script` when it is the
+/// installed distrobox-create, then `config_files` and last the environment
+pub fn load_distrobox_config(
+    config_files: &[PathBuf],
+    env: &HashMap<String, String>,
+    create_script: Option<&str>,
+    (uid, gid): (String, String),
+) -> HashMap<String, String> {
+    let mut config: HashMap<String, String> = DEFAULTS
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    if let Some(script) = create_script {
+        config.extend(read_script_defaults(script));
     }
+    config.insert(
+        "container_user_home".to_string(),
+        env.get("HOME")
+            .filter(|home| !home.is_empty())
+            .cloned()
+            .unwrap_or("/".to_string()),
+    );
+    config.insert(
+        "container_user_name".to_string(),
+        env.get("USER").cloned().unwrap_or_default(),
+    );
+    config.insert(
+        "rootful".to_string(),
+        if uid == "0" { "1" } else { "0" }.to_string(),
+    );
+    config.insert("container_user_uid".to_string(), uid.clone());
+    config.insert("container_user_gid".to_string(), gid);
 
-    let stdout = String::from_utf8(output.stdout)?;
-    let lines: Vec<&str> = stdout.lines().collect();
+    for config_file in config_files {
+        if let Ok(content) = std::fs::read_to_string(config_file) {
+            read_assignments(&content, env, &mut config);
+        }
+    }
+
+    if uid != "0" {
+        let sudo_program = env
+            .get("DBX_SUDO_PROGRAM")
+            .filter(|program| !program.is_empty())
+            .or(config.get("distrobox_sudo_program"))
+            .cloned()
+            .unwrap_or("sudo".to_string());
+        config.insert("distrobox_sudo_program".to_string(), sudo_program);
+    }
+    match config.get("non_interactive").map(String::as_str) {
+        Some("true") => config.insert("non_interactive".to_string(), "1".to_string()),
+        Some("false") => config.insert("non_interactive".to_string(), "0".to_string()),
+        _ => None,
+    };
+    for (variable, key) in ENV_OVERRIDES {
+        if let Some(value) = env.get(*variable).filter(|value| !value.is_empty()) {
+            config.insert(key.to_string(), value.clone());
+        }
+    }
+    config
+}
 
-    let mut variables = HashMap::new();
-    for line in lines {
-        let split: Vec<&str> = line.splitn(2, '=').collect();
-        if split.len() == 2 {
-            let key = split[0].to_string();
-            let value = split[1].to_string();
-            variables.insert(key, value);
+/// Constant `key="value"` defaults of a distrobox-create script, so a newer distrobox
+/// brings its own default image and version
+fn read_script_defaults(script: &str) -> HashMap<String, String> {
+    let mut assignments = HashMap::new();
+    for line in script.lines() {
+        let line = line.trim();
+        let Some((key, value)) = line.strip_prefix("export ").unwrap_or(line).split_once('=')
+        else {
+            continue;
+        };
+        if assignments.contains_key(key) || !DEFAULTS.iter().any(|(name, _)| *name == key) {
+            continue;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        if !value.contains(['$', '`', '"', '\'', ' ']) {
+            assignments.insert(key.to_string(), value.to_string());
         }
     }
+    assignments
+}
 
-    Ok(variables)
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Apply the `key=value` lines of a distrobox.conf, a shell snippet, to `config`;
+/// anything that is not a plain assignment is ignored
+fn read_assignments(
+    content: &str,
+    env: &HashMap<String, String>,
+    config: &mut HashMap<String, String>,
+) {
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        if !is_variable_name(key) {
+            continue;
+        }
+        let value = read_shell_word(value, env, config);
+        config.insert(key.to_string(), value);
+    }
 }
 
-pub fn get_distrobox_config() -> HashMap<String, String> {
-    // From https://github.com/89luca89/distrobox/blob/main/distrobox-create
-    let script = r#"
-# Defaults
-export container_additional_packages=""
-export container_always_pull=0
-export container_clone=""
-export container_generate_entry=1
-export container_home_prefix=""
-export container_image=""
-export container_image_default="registry.fedoraproject.org/fedora-toolbox:38"
-export container_init_hook=""
-export container_manager="autodetect"
-export container_manager_additional_flags=""
-export container_name=""
-export container_name_default="my-distrobox"
-export container_pre_init_hook=""
-export container_user_custom_home=""
-export container_user_gid="$(id -rg)"
-export container_user_home="${HOME:-"/"}"
-export container_user_name="${USER}"
-export container_user_uid="$(id -ru)"
-export dryrun=0
-export init=0
-export non_interactive=0
-export nvidia=0
-export unshare_ipc=0
-export unshare_netns=0"#
-        .to_string()
-        + r#"
-# Use cd + dirname + pwd so that we do not have relative paths in mount points
-# We're not using "realpath" here so that symlinks are not resolved this way
-# "realpath" would break situations like Nix or similar symlink based package
-# management.
-distrobox_entrypoint_path="$(cd "$(dirname "${0}")" && pwd)/distrobox-init"
-distrobox_export_path="$(cd "$(dirname "${0}")" && pwd)/distrobox-export"
-distrobox_genentry_path="$(cd "$(dirname "${0}")" && pwd)/distrobox-generate-entry"
-distrobox_hostexec_path="$(cd "$(dirname "${0}")" && pwd)/distrobox-host-exec"
-# In case some of the scripts are not in the same path as create, let's search
-# in PATH for them.
-[ ! -e "${distrobox_entrypoint_path}" ] && distrobox_entrypoint_path="$(command -v distrobox-init)"
-[ ! -e "${distrobox_export_path}" ] && distrobox_export_path="$(command -v distrobox-export)"
-[ ! -e "${distrobox_genentry_path}" ] && distrobox_genentry_path="$(command -v distrobox-generate-entry)"
-[ ! -e "${distrobox_hostexec_path}" ] && distrobox_hostexec_path="$(command -v distrobox-host-exec)"
-# If the user runs this script as root in a login shell, set rootful=1.
-# There's no need for them to pass the --root flag option in such cases.
-[ "${container_user_uid}" -eq 0 ] && rootful=1 || rootful=0
-verbose=0
-version="1.5.0.2"
-
-# Source configuration files, this is done in an hierarchy so local files have
-# priority over system defaults
-# leave priority to environment variables.
-config_files="
-	/usr/share/distrobox/distrobox.conf
-	/usr/share/defaults/distrobox/distrobox.conf
-	/usr/etc/distrobox/distrobox.conf
-	/usr/local/share/distrobox/distrobox.conf
-	/etc/distrobox/distrobox.conf
-	${XDG_CONFIG_HOME:-"${HOME}/.config"}/distrobox/distrobox.conf
-	${HOME}/.distroboxrc
-"
-for config_file in ${config_files}; do
-	# Shellcheck will give error for sourcing a variable file as it cannot follow
-	# it. We don't care so let's disable this linting for now.
-	# shellcheck disable=SC1090
-	[ -e "${config_file}" ] && . "${config_file}"
-done
-# If we're running this script as root -- as in logged in in the shell as root
-# user, and not via SUDO/DOAS --, we don't need to set distrobox_sudo_program
-# as it's meaningless for this use case.
-if [ "${container_user_uid}" -ne 0 ]; then
-	# If the DBX_SUDO_PROGRAM/distrobox_sudo_program variable was set by the
-	# user, use its value instead of "sudo". But only if not running the script
-	# as root (UID 0).
-	distrobox_sudo_program=${DBX_SUDO_PROGRAM:-${distrobox_sudo_program:-"sudo"}}
-fi
-# Fixup non_interactive=[true|false], in case we find it in the config file(s)
-[ "${non_interactive}" = "true" ] && non_interactive=1
-[ "${non_interactive}" = "false" ] && non_interactive=0
-
-[ -n "${DBX_CONTAINER_ALWAYS_PULL}" ] && container_always_pull="${DBX_CONTAINER_ALWAYS_PULL}"
-[ -n "${DBX_CONTAINER_CUSTOM_HOME}" ] && container_user_custom_home="${DBX_CONTAINER_CUSTOM_HOME}"
-[ -n "${DBX_CONTAINER_HOME_PREFIX}" ] && container_home_prefix="${DBX_CONTAINER_HOME_PREFIX}"
-[ -n "${DBX_CONTAINER_IMAGE}" ] && container_image="${DBX_CONTAINER_IMAGE}"
-[ -n "${DBX_CONTAINER_MANAGER}" ] && container_manager="${DBX_CONTAINER_MANAGER}"
-[ -n "${DBX_CONTAINER_NAME}" ] && container_name="${DBX_CONTAINER_NAME}"
-[ -n "${DBX_NON_INTERACTIVE}" ] && non_interactive="${DBX_NON_INTERACTIVE}"
-[ -n "${DBX_container_generate_entry}" ] && container_generate_entry="${DBX_container_generate_entry}"
-"#;
-    extract_environment_variables(&script).unwrap_or_else(|_| HashMap::new())
+/// First word of `input` with quotes removed and variables expanded, like `sh` would
+fn read_shell_word(
+    input: &str,
+    env: &HashMap<String, String>,
+    config: &HashMap<String, String>,
+) -> String {
+    let mut word = String::new();
+    let mut chars = input.chars().peekable();
+    let mut in_double_quote = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_double_quote = !in_double_quote,
+            '\'' if !in_double_quote => {
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    word.push(c);
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            '$' => {
+                let mut expression = String::new();
+                if chars.peek() == Some(&'{') {
+                    chars.next();
+                    let mut depth = 0;
+                    for c in chars.by_ref() {
+                        match c {
+                            '{' => depth += 1,
+                            '}' if depth == 0 => break,
+                            '}' => depth -= 1,
+                            _ => (),
+                        }
+                        expression.push(c);
+                    }
+                } else {
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                        expression.push(c);
+                    }
+                }
+                if expression.is_empty() {
+                    word.push('$');
+                    continue;
+                }
+                let (name, default) = match expression.split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (expression.as_str(), None),
+                };
+                let value = config
+                    .get(name)
+                    .or(env.get(name))
+                    .filter(|value| !value.is_empty() || default.is_none());
+                match (value, default) {
+                    (Some(value), _) => word.push_str(value),
+                    (None, Some(default)) => word.push_str(&read_shell_word(default, env, config)),
+                    (None, None) => (),
+                }
+            }
+            c if c.is_whitespace() && !in_double_quote => break,
+            c => word.push(c),
+        }
+    }
+    word
 }
 
 #[cfg(test)]
@@ -127,16 +295,80 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_extract_environment_variables() {
-        let script = r#"
-export TEST_VAR1="Hello"
-export TEST_VAR2='World'
-export TEST_VAR3=!"#;
-
-        let result = extract_environment_variables(script).unwrap();
-        assert_eq!(result.get("TEST_VAR1"), Some(&"Hello".to_string()));
-        assert_eq!(result.get("TEST_VAR2"), Some(&"World".to_string()));
-        assert_eq!(result.get("TEST_VAR3"), Some(&"!".to_string()));
+    fn test_read_shell_word() {
+        let env = HashMap::from([("HOME".to_string(), "/home/user".to_string())]);
+        let config = HashMap::from([("container_name".to_string(), "box".to_string())]);
+        for (input, expected) in [
+            (r#""Hello""#, "Hello"),
+            ("'World'", "World"),
+            ("! # comment", "!"),
+            (r#""${HOME}/.local""#, "/home/user/.local"),
+            ("$HOME/x", "/home/user/x"),
+            (
+                r#""${XDG_DATA_HOME:-${HOME}/.local/share}""#,
+                "/home/user/.local/share",
+            ),
+            (r#""$container_name-2""#, "box-2"),
+            ("'$HOME'", "$HOME"),
+            (r#""a \"b\"""#, "a \"b\""),
+        ] {
+            assert_eq!(read_shell_word(input, &env, &config), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_load_distrobox_config() {
+        let dir = std::env::temp_dir().join(format!(
+            "distrobox-boost-test-distrobox-config-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let system = dir.join("system.conf");
+        let user = dir.join("user.conf");
+        std::fs::write(
+            &system,
+            "# system defaults\ncontainer_manager=\"docker\"\ncontainer_always_pull=1\nnon_interactive=true\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &user,
+            "export container_manager=\"podman\"\ncontainer_home_prefix=\"${HOME}/boxes\"\nif true; then\n",
+        )
+        .unwrap();
+        let script = "\
+container_image_default=\"registry.fedoraproject.org/fedora-toolbox:40\"
+version=\"1.7.2.1\"
+container_user_home=\"${HOME:-\"/\"}\"
+";
+        let env = HashMap::from([
+            ("HOME".to_string(), "/home/user".to_string()),
+            ("USER".to_string(), "user".to_string()),
+            ("DBX_CONTAINER_ALWAYS_PULL".to_string(), "0".to_string()),
+            ("DBX_CONTAINER_NAME".to_string(), String::new()),
+        ]);
+
+        let config = load_distrobox_config(
+            &[system, user, dir.join("missing.conf")],
+            &env,
+            Some(script),
+            ("1000".to_string(), "1000".to_string()),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            config["container_image_default"],
+            "registry.fedoraproject.org/fedora-toolbox:40"
+        );
+        assert_eq!(config["version"], "1.7.2.1");
+        assert_eq!(config["container_manager"], "podman");
+        assert_eq!(config["container_home_prefix"], "/home/user/boxes");
+        assert_eq!(config["container_always_pull"], "0");
+        assert_eq!(config["container_name"], "");
+        assert_eq!(config["non_interactive"], "1");
+        assert_eq!(config["container_user_home"], "/home/user");
+        assert_eq!(config["container_user_uid"], "1000");
+        assert_eq!(config["rootful"], "0");
+        assert_eq!(config["distrobox_sudo_program"], "sudo");
     }
 
     #[test]
@@ -0,0 +1,374 @@
+// check distrobox-assemble files before anything is built from them
+
+use super::assemble::{ASSEMBLE_BOOL_KEYS, ASSEMBLE_KEYS};
+use crate::utils::ini::{IniDocument, IniItem};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    /// Reported, but the file is still used
+    Warning,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Empty when the content did not come from a file
+    pub file: String,
+    pub line: usize,
+    pub section: Option<String>,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.file.is_empty() {
+            write!(f, "line {}: ", self.line)?;
+        } else {
+            write!(f, "{}:{}: ", self.file, self.line)?;
+        }
+        match self.severity {
+            Severity::Error => write!(f, "error: ")?,
+            Severity::Warning => write!(f, "warning: ")?,
+        }
+        if let Some(section) = &self.section {
+            write!(f, "[{}] ", section)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, "{}: ", key)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Where a container is defined and where its image is set
+struct ContainerLocation {
+    file: String,
+    section_line: usize,
+    image: Option<(usize, String)>,
+}
+
+fn diagnostic(
+    severity: Severity,
+    file: &str,
+    line: usize,
+    section: &Option<String>,
+    key: Option<&str>,
+    message: impl Into<String>,
+) -> Diagnostic {
+    Diagnostic {
+        severity,
+        file: file.to_string(),
+        line,
+        section: section.clone(),
+        key: key.map(|key| key.to_string()),
+        message: message.into(),
+    }
+}
+
+/// Every problem of the `(file, content)` pairs, as if they were given together with
+/// `--input`: one file on its own and containers across files, like duplicate names
+/// and images that lead back to their own container
+pub fn validate_distrobox_assemble_files(files: &[(&str, &str)]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut containers: BTreeMap<String, ContainerLocation> = BTreeMap::new();
+
+    for (file, content) in files {
+        let mut file_containers: BTreeMap<String, ContainerLocation> = BTreeMap::new();
+        let mut includes: Vec<(usize, String, String)> = vec![];
+        for item in IniDocument::parse(content).items() {
+            match item {
+                IniItem::Section { line, name } => {
+                    // a section repeated in one file is merged, like distrobox-assemble does
+                    file_containers
+                        .entry(name)
+                        .or_insert_with(|| ContainerLocation {
+                            file: file.to_string(),
+                            section_line: line,
+                            image: None,
+                        });
+                }
+                IniItem::Entry {
+                    line,
+                    section: None,
+                    key,
+                    ..
+                } => diagnostics.push(diagnostic(
+                    Severity::Error,
+                    file,
+                    line,
+                    &None,
+                    Some(&key),
+                    "key outside of a section",
+                )),
+                IniItem::Entry {
+                    line,
+                    section: Some(name),
+                    key,
+                    value,
+                } => {
+                    let section = Some(name.clone());
+                    if !ASSEMBLE_KEYS.contains(&key.as_str()) {
+                        diagnostics.push(diagnostic(
+                            Severity::Warning,
+                            file,
+                            line,
+                            &section,
+                            Some(&key),
+                            "unknown key, it is kept as is",
+                        ));
+                    } else if ASSEMBLE_BOOL_KEYS.contains(&key.as_str())
+                        && value.parse::<bool>().is_err()
+                    {
+                        diagnostics.push(diagnostic(
+                            Severity::Error,
+                            file,
+                            line,
+                            &section,
+                            Some(&key),
+                            format!("\"{}\" is not true or false", value),
+                        ));
+                    } else if key == "include" {
+                        includes.push((line, name, value.trim().to_string()));
+                    } else if key == "image" {
+                        if value.trim().is_empty() {
+                            diagnostics.push(diagnostic(
+                                Severity::Error,
+                                file,
+                                line,
+                                &section,
+                                Some(&key),
+                                "image is empty",
+                            ));
+                        } else if let Some(container) = file_containers.get_mut(&name) {
+                            container.image = Some((line, value));
+                        }
+                    }
+                }
+                IniItem::Invalid {
+                    line,
+                    section,
+                    text,
+                } => diagnostics.push(diagnostic(
+                    Severity::Error,
+                    file,
+                    line,
+                    &section,
+                    None,
+                    format!("\"{}\" is not a section or key=value", text),
+                )),
+            }
+        }
+        diagnostics.extend(check_includes(file, &includes, &file_containers));
+
+        // included sections are templates, only the other ones are containers
+        for (name, container) in file_containers {
+            if includes.iter().any(|(_, _, include)| *include == name) {
+                continue;
+            }
+            if let Some(other) = containers.get(&name) {
+                diagnostics.push(diagnostic(
+                    Severity::Error,
+                    file,
+                    container.section_line,
+                    &Some(name.clone()),
+                    None,
+                    format!(
+                        "container is already defined in {} line {}",
+                        other.file, other.section_line
+                    ),
+                ));
+                continue;
+            }
+            containers.insert(name, container);
+        }
+    }
+
+    diagnostics.extend(find_image_cycles(&containers));
+    diagnostics
+}
+
+/// `include=` lines, as `(line, section, included section)`, that name a section missing
+/// from the file or that lead back to their own section
+fn check_includes(
+    file: &str,
+    includes: &[(usize, String, String)],
+    sections: &BTreeMap<String, ContainerLocation>,
+) -> Vec<Diagnostic> {
+    fn find_path(
+        from: &str,
+        to: &str,
+        includes: &[(usize, String, String)],
+        path: &mut Vec<String>,
+    ) -> bool {
+        if from == to {
+            path.push(from.to_string());
+            return true;
+        }
+        if path.iter().any(|section| section == from) {
+            return false;
+        }
+        path.push(from.to_string());
+        for (_, section, include) in includes {
+            if section == from && find_path(include, to, includes, path) {
+                return true;
+            }
+        }
+        path.pop();
+        false
+    }
+
+    let mut diagnostics = vec![];
+    for (line, section, include) in includes {
+        let message = if !sections.contains_key(include) {
+            format!("there is no section \"{}\" in this file", include)
+        } else {
+            let mut path = vec![section.clone()];
+            if !find_path(include, section, includes, &mut path) {
+                continue;
+            }
+            format!("include leads back to the section: {}", path.join(" -> "))
+        };
+        diagnostics.push(diagnostic(
+            Severity::Error,
+            file,
+            *line,
+            &Some(section.clone()),
+            Some("include"),
+            message,
+        ));
+    }
+    diagnostics
+}
+
+/// Containers whose image is another container that, image after image, comes back to
+/// them; an image with the name of its own container is the registry image of that name
+fn find_image_cycles(containers: &BTreeMap<String, ContainerLocation>) -> Vec<Diagnostic> {
+    let images: HashMap<&str, &str> = containers
+        .iter()
+        .filter_map(|(name, container)| {
+            let (_, image) = container.image.as_ref()?;
+            Some((name.as_str(), image.as_str()))
+        })
+        .collect();
+
+    let mut diagnostics = vec![];
+    for (name, container) in containers {
+        let mut path = vec![name.as_str()];
+        let mut current = name.as_str();
+        while let Some(&image) = images.get(current) {
+            if image == current || path[1..].contains(&image) {
+                break;
+            }
+            path.push(image);
+            if image == name {
+                let (line, _) = container.image.as_ref().unwrap();
+                diagnostics.push(diagnostic(
+                    Severity::Error,
+                    &container.file,
+                    *line,
+                    &Some(name.clone()),
+                    Some("image"),
+                    format!("image leads back to the container: {}", path.join(" -> ")),
+                ));
+                break;
+            }
+            current = image;
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_distrobox_assemble_file() {
+        let content = "\
+pull=true
+[dev]
+image=ubuntu
+init=yes
+future_key=1
+oops
+
+[empty]
+image=\"\"
+";
+        let diagnostics = validate_distrobox_assemble_files(&[("dev.ini", content)]);
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "dev.ini:1: error: pull: key outside of a section",
+                "dev.ini:4: error: [dev] init: \"yes\" is not true or false",
+                "dev.ini:5: warning: [dev] future_key: unknown key, it is kept as is",
+                "dev.ini:6: error: [dev] \"oops\" is not a section or key=value",
+                "dev.ini:9: error: [empty] image: image is empty",
+            ]
+        );
+        assert!(!diagnostics[2].is_error());
+
+        let content = std::fs::read_to_string("tests/files/example.ini").unwrap();
+        assert!(validate_distrobox_assemble_files(&[("example.ini", &content)]).is_empty());
+    }
+
+    #[test]
+    fn test_validate_distrobox_assemble_include() {
+        let content = "\
+[base]
+image=fedora
+
+[a]
+include=b
+
+[b]
+include=a
+
+[c]
+include=missing
+include=base
+";
+        let diagnostics = validate_distrobox_assemble_files(&[("inc.ini", content)]);
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "inc.ini:5: error: [a] include: include leads back to the section: a -> b -> a",
+                "inc.ini:8: error: [b] include: include leads back to the section: b -> a -> b",
+                "inc.ini:11: error: [c] include: there is no section \"missing\" in this file",
+            ]
+        );
+
+        // the same template in two files is not a duplicate container
+        let a = "[base]\nimage=fedora\n\n[app]\ninclude=base\n";
+        let b = "[base]\nimage=fedora\n\n[tool]\ninclude=base\n";
+        assert!(validate_distrobox_assemble_files(&[("a.ini", a), ("b.ini", b)]).is_empty());
+    }
+
+    #[test]
+    fn test_validate_distrobox_assemble_files() {
+        let a = "[base]\nimage=fedora\n\n[app]\nimage=tools\n";
+        let b = "[tools]\nimage=app\n\n[base]\nimage=fedora\n\n[fedora]\nimage=fedora\n";
+        let diagnostics = validate_distrobox_assemble_files(&[("a.ini", a), ("b.ini", b)]);
+        let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "b.ini:4: error: [base] container is already defined in a.ini line 1",
+                "a.ini:5: error: [app] image: image leads back to the container: app -> tools -> app",
+                "b.ini:2: error: [tools] image: image leads back to the container: tools -> app -> tools",
+            ]
+        );
+    }
+}
@@ -1,70 +1,533 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
 
-use crate::config::{get_distrobox_mode, get_distrobox_boost_image_prefix};
+use crate::config::{
+    get_distrobox_assemble_pull, get_distrobox_boost_image_prefix, get_distrobox_mode,
+    set_distrobox_assemble_pull, set_distrobox_setup_marker,
+};
 use crate::container_tree::builder::build_container_trees;
 use crate::container_tree::distrobox_assemble_tree::{trees_to_distrobox_assemble, ContainerNode};
+use crate::distro::package_diff::PackageChange;
+use crate::distrobox::capabilities::DistroboxCapabilities;
 use crate::distrobox::parser::assemble::ContainerAssembleData;
-use crate::oci::image_builder::build_image;
-use crate::utils::command_helper::run_command;
+use crate::oci::container_runtime::ContainerRuntime;
+use crate::oci::containerfile::generate_containerfile;
+use crate::oci::image_builder::{
+    build_image, get_dockerfile_image_name, get_image_name_part, pre_build_image, update_image,
+    DockerfileOptions,
+};
+use crate::utils::command_helper::{
+    print_with_prefix, run_command, set_output_prefix, CommandError,
+};
+use crate::utils::mutex_lock::Semaphore;
 
-fn build_image_by_tree(container_runner: &str, tree: &mut ContainerNode) {
-    fn tree_to_image_map(container_runner: &str, tree: &mut ContainerNode, node_level: usize) {
-        let image = &tree.container_assemble_data.image.clone();
-        println!("Build image: {}", &image);
+/// Build images and assemble files for the installed distrobox: pick the setup marker
+/// its distrobox-init checks and write `pull` only when its version reads it, with a
+/// warning for what it does not support
+pub fn apply_distrobox_capabilities(capabilities: &DistroboxCapabilities) {
+    match &capabilities.version {
+        Some(version) => println!("distrobox {}", version),
+        None => {
+            eprintln!("Warning: distrobox not found, images are built for distrobox 1.4 or later")
+        }
+    }
+    let (setup_marker, warning) = capabilities.choose_setup_marker();
+    if let Some(warning) = warning {
+        eprintln!("Warning: {}", warning);
+    }
+    set_distrobox_setup_marker(setup_marker);
+    let (assemble_pull, warning) = capabilities.choose_assemble_pull();
+    if let Some(warning) = warning {
+        eprintln!("Warning: {}", warning);
+    }
+    set_distrobox_assemble_pull(assemble_pull);
+}
+
+/// `pull` of a built container: false so distrobox uses the built image, or the value
+/// of the file when the installed distrobox does not read `pull`
+fn get_built_pull(pull: Option<bool>, assemble_pull: bool) -> Option<bool> {
+    if assemble_pull {
+        Some(false)
+    } else {
+        pull
+    }
+}
+
+/// Image that `build` tags for `container_name`
+pub fn get_release_image_name(container_name: &str) -> String {
+    format!(
+        "{}/release/{}",
+        &get_distrobox_boost_image_prefix(),
+        get_image_name_part(container_name)
+    )
+}
+
+fn get_dockerfile_options(data: &ContainerAssembleData) -> DockerfileOptions {
+    DockerfileOptions {
+        context: data.dockerfile_context.clone(),
+        build_args: data.dockerfile_build_args.clone().unwrap_or_default(),
+        target: data.dockerfile_target.clone(),
+        secrets: data.dockerfile_secret.clone().unwrap_or_default(),
+    }
+}
+
+fn build_image_by_trees(
+    runtime: &dyn ContainerRuntime,
+    trees: &mut [ContainerNode],
+    jobs: usize,
+) -> Result<(), CommandError> {
+    /// Run the `pre_build_cmd` of a container, a blank command runs nothing
+    fn run_pre_build_cmd(pre_build_cmd: &str) -> Result<(), CommandError> {
+        let mut parts = pre_build_cmd.split_whitespace();
+        let Some(command_name) = parts.next() else {
+            return Ok(());
+        };
+        let args = parts.collect::<Vec<&str>>();
+        run_command(command_name, &args, true).map(|_| ())
+    }
+    /// Build `tree` and its children, returns the failed containers with their errors,
+    /// children of a failed container are not built
+    fn tree_to_image_map(
+        runtime: &dyn ContainerRuntime,
+        tree: &mut ContainerNode,
+        image: &str,
+        semaphore: &Semaphore,
+        jobs: usize,
+    ) -> Vec<(String, CommandError)> {
+        if jobs > 1 {
+            set_output_prefix(&format!("[{}] ", &tree.container_name));
+        }
+        print_with_prefix(&format!("Build image: {}", &image));
         let empty_vec = vec![];
         let packages = tree
             .container_assemble_data
             .packages
             .as_ref()
             .unwrap_or(&empty_vec);
-        println!("Packages: {:?}", &packages);
-        let new_image = format!("{}/release/{}", &get_distrobox_boost_image_prefix(), &image);
-        println!(
+        print_with_prefix(&format!("Packages: {:?}", &packages));
+        let new_image = get_release_image_name(&tree.container_name);
+        print_with_prefix(&format!(
             "Build container name: {} to {}",
             &tree.container_name, &new_image
-        );
-        if tree.container_assemble_data.pre_build_cmd.is_some() {
-            let pre_build_cmd = tree.container_assemble_data.pre_build_cmd.clone().unwrap();
-            println!("Pre build command: {}", &pre_build_cmd);
-            let command_name = &pre_build_cmd.split_whitespace().next().unwrap();
-            let args = &pre_build_cmd
-                .split_whitespace()
-                .skip(1)
-                .collect::<Vec<&str>>();
-            run_command(command_name, args, true).unwrap();
+        ));
+        let result = semaphore.execute(|| {
+            if let Some(pre_build_cmd) = &tree.container_assemble_data.pre_build_cmd {
+                print_with_prefix(&format!("Pre build command: {}", pre_build_cmd));
+                run_pre_build_cmd(pre_build_cmd)?;
+            }
+            let image = pre_build_image(
+                runtime,
+                &tree.container_name,
+                image,
+                &get_distrobox_boost_image_prefix(),
+                &get_dockerfile_options(&tree.container_assemble_data),
+            )?;
+            build_image(
+                runtime,
+                &new_image,
+                &image,
+                &tree.container_assemble_data.package_manager,
+                packages,
+                &get_distrobox_boost_image_prefix(),
+                get_distrobox_mode(),
+            )
+        });
+        if let Err(e) = result {
+            return vec![(tree.container_name.clone(), e)];
         }
-        build_image(
-            container_runner,
-            &new_image,
-            &image,
-            &tree.container_assemble_data.package_manager,
-            packages,
-            &get_distrobox_boost_image_prefix(),
-            get_distrobox_mode(),
-        )
-        .unwrap();
         tree.container_assemble_data.image = new_image.clone();
-        for child in &mut tree.children {
-            tree_to_image_map(container_runner, child, node_level + 1);
-        }
+        // children are layered on top of the image built for their parent,
+        // siblings only depend on it and can be built at the same time
+        thread::scope(|s| {
+            let handles = tree
+                .children
+                .iter_mut()
+                .map(|child| {
+                    let new_image = &new_image;
+                    s.spawn(move || tree_to_image_map(runtime, child, new_image, semaphore, jobs))
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+    let semaphore = Semaphore::new(jobs);
+    let failures = thread::scope(|s| {
+        let handles = trees
+            .iter_mut()
+            .map(|tree| {
+                let semaphore = &semaphore;
+                s.spawn(move || {
+                    let image = tree.container_assemble_data.image.clone();
+                    tree_to_image_map(runtime, tree, &image, semaphore, jobs)
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    if failures.is_empty() {
+        return Ok(());
     }
-    tree_to_image_map(container_runner, tree, 0);
+    Err(CommandError {
+        stdout: String::new(),
+        stderr: failures
+            .iter()
+            .map(|(container_name, e)| format!("{}: {}", container_name, e.stderr.trim_end()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        status: failures.iter().find_map(|(_, e)| e.status),
+        inner: None,
+    })
 }
 
 pub fn build_distrobox_assemble_data(
-    container_runner: &str,
+    runtime: &dyn ContainerRuntime,
     data: &HashMap<String, ContainerAssembleData>,
-) -> HashMap<String, ContainerAssembleData> {
+    jobs: usize,
+) -> Result<HashMap<String, ContainerAssembleData>, CommandError> {
     let mut trees = build_container_trees(data);
 
-    for tree in &mut trees {
-        build_image_by_tree(container_runner, tree);
-    }
+    build_image_by_trees(runtime, &mut trees, jobs)?;
 
     let mut new_data = trees_to_distrobox_assemble(&trees);
     for (key, value) in new_data.iter_mut() {
-        value.pull = Some(false);
+        value.pull = get_built_pull(data[key].pull, get_distrobox_assemble_pull());
         value.packages = data[key].packages.clone();
     }
-    new_data
+    Ok(new_data)
+}
+
+/// Write `<container>.Containerfile` for every image `build_distrobox_assemble_data` builds,
+/// parents have to be built first and are returned before their children
+pub fn write_containerfiles(
+    runtime: &dyn ContainerRuntime,
+    data: &HashMap<String, ContainerAssembleData>,
+    dir: &Path,
+) -> Result<Vec<PathBuf>, CommandError> {
+    fn write_tree(
+        runtime: &dyn ContainerRuntime,
+        tree: &ContainerNode,
+        image: &str,
+        dir: &Path,
+        paths: &mut Vec<PathBuf>,
+    ) -> Result<(), CommandError> {
+        let new_image = get_release_image_name(&tree.container_name);
+        let file_name = format!(
+            "{}.Containerfile",
+            tree.container_name.replace([':', '/'], "_")
+        );
+        let mut header = vec![];
+        let mut base_image = image.to_string();
+        if let Some(dockerfile_path) = image.strip_prefix("dockerfile://") {
+            let options = get_dockerfile_options(&tree.container_assemble_data);
+            base_image = get_dockerfile_image_name(
+                &tree.container_name,
+                &get_distrobox_boost_image_prefix(),
+            );
+            let mut cmd = vec![
+                "podman build -t".to_string(),
+                base_image.clone(),
+                "-f".to_string(),
+                dockerfile_path.to_string(),
+            ];
+            cmd.extend(options.to_args());
+            cmd.push(options.get_context(dockerfile_path));
+            header.push(format!("# {}", cmd.join(" ")));
+        }
+        header.push(format!(
+            "# podman build -t {} -f {} .",
+            new_image, file_name
+        ));
+        let empty_vec = vec![];
+        let packages = tree
+            .container_assemble_data
+            .packages
+            .as_ref()
+            .unwrap_or(&empty_vec);
+        let containerfile = generate_containerfile(
+            runtime,
+            &base_image,
+            &tree.container_assemble_data.package_manager,
+            packages,
+            get_distrobox_mode(),
+        )?;
+        let path = dir.join(file_name);
+        fs::write(&path, header.join("\n") + "\n" + &containerfile)?;
+        paths.push(path);
+        for child in &tree.children {
+            write_tree(runtime, child, &new_image, dir, paths)?;
+        }
+        Ok(())
+    }
+    fs::create_dir_all(dir)?;
+    let mut paths = vec![];
+    for tree in build_container_trees(data) {
+        write_tree(
+            runtime,
+            &tree,
+            &tree.container_assemble_data.image,
+            dir,
+            &mut paths,
+        )?;
+    }
+    Ok(paths)
+}
+
+/// Upgrade the packages of every built container, keyed by container name
+pub fn update_distrobox_assemble_data(
+    runtime: &dyn ContainerRuntime,
+    data: &HashMap<String, ContainerAssembleData>,
+) -> Result<BTreeMap<String, Vec<PackageChange>>, CommandError> {
+    let mut changes = BTreeMap::new();
+    let mut names: Vec<&String> = data.keys().collect();
+    names.sort();
+    for name in names {
+        let image = get_release_image_name(name);
+        let package_changes = update_image(runtime, &image, &data[name].package_manager)?;
+        changes.insert(name.clone(), package_changes);
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oci::fake_runtime::{FakeRuntime, UBUNTU_OS_RELEASE};
+
+    fn create_test_container_assemble_data(packages: &[&str]) -> ContainerAssembleData {
+        ContainerAssembleData {
+            image: "ubuntu:latest".to_string(),
+            packages: Some(packages.iter().map(|s| s.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    fn test_build_distrobox_assemble_data_with_jobs(jobs: usize) {
+        let runtime = FakeRuntime::new().with_image("ubuntu:latest", UBUNTU_OS_RELEASE);
+        let mut data = HashMap::new();
+        data.insert(
+            "dev1".to_string(),
+            create_test_container_assemble_data(&["git", "vim"]),
+        );
+        data.insert(
+            "dev2".to_string(),
+            create_test_container_assemble_data(&["git", "htop"]),
+        );
+
+        let result = build_distrobox_assemble_data(&runtime, &data, jobs).unwrap();
+
+        let prefix = get_distrobox_boost_image_prefix();
+        assert_eq!(result.len(), 2);
+        for (name, packages) in [("dev1", ["git", "vim"]), ("dev2", ["git", "htop"])] {
+            let value = &result[name];
+            assert_eq!(value.image, format!("{}/release/{}", prefix, name));
+            assert_eq!(value.pull, Some(false));
+            assert_eq!(value.packages, data[name].packages);
+
+            // the common package is installed once in the shared parent layer
+            let image = runtime.image(&value.image).unwrap();
+            for package in packages {
+                let cmd = format!("apt-get install -y {}", package);
+                assert_eq!(image.commands.iter().filter(|c| **c == cmd).count(), 1);
+            }
+        }
+        let dev1 = runtime.image(&result["dev1"].image).unwrap();
+        assert!(!dev1
+            .commands
+            .contains(&"apt-get install -y htop".to_string()));
+        assert!(runtime
+            .image(&format!("{}/release/ubuntu:latest", prefix))
+            .is_some());
+    }
+
+    #[test]
+    fn test_build_distrobox_assemble_data() {
+        test_build_distrobox_assemble_data_with_jobs(1);
+    }
+
+    #[test]
+    fn test_build_distrobox_assemble_data_parallel() {
+        test_build_distrobox_assemble_data_with_jobs(4);
+    }
+
+    #[test]
+    fn test_build_distrobox_assemble_data_failure() {
+        let runtime = FakeRuntime::new()
+            .with_image("ubuntu:latest", UBUNTU_OS_RELEASE)
+            .with_failure("install -y not-a-package");
+        let mut data = HashMap::new();
+        data.insert(
+            "dev1".to_string(),
+            ContainerAssembleData {
+                // a blank pre-build command runs nothing
+                pre_build_cmd: Some("  ".to_string()),
+                ..create_test_container_assemble_data(&["git", "vim"])
+            },
+        );
+        data.insert(
+            "dev2".to_string(),
+            create_test_container_assemble_data(&["git", "not-a-package"]),
+        );
+
+        let e = build_distrobox_assemble_data(&runtime, &data, 2).unwrap_err();
+
+        // the failed container is named, its sibling is still built
+        assert!(e.stderr.starts_with("dev2: "));
+        assert!(!e.stderr.contains("dev1"));
+        assert!(runtime.image(&get_release_image_name("dev1")).is_some());
+        assert!(runtime.image(&get_release_image_name("dev2")).is_none());
+    }
+
+    #[test]
+    fn test_get_built_pull() {
+        assert_eq!(get_built_pull(None, true), Some(false));
+        assert_eq!(get_built_pull(Some(true), true), Some(false));
+        // a distrobox that does not read pull gets the file as it was
+        assert_eq!(get_built_pull(None, false), None);
+        assert_eq!(get_built_pull(Some(true), false), Some(true));
+    }
+
+    #[test]
+    fn test_update_distrobox_assemble_data() {
+        let runtime = FakeRuntime::new()
+            .with_image("ubuntu:latest", UBUNTU_OS_RELEASE)
+            .with_output(
+                "apt list",
+                "Listing...\nbash/jammy,now 5.1 amd64 [installed]\ngit/jammy,now 2.34 amd64 [installed]\n",
+            )
+            .with_layer_output(
+                "apt-get update && apt-get upgrade",
+                "apt list",
+                "Listing...\nbash/jammy-updates,now 5.2 amd64 [installed]\ngit/jammy,now 2.34 amd64 [installed]\n",
+            );
+        let mut data = HashMap::new();
+        data.insert(
+            "dev".to_string(),
+            create_test_container_assemble_data(&["git"]),
+        );
+        let built = build_distrobox_assemble_data(&runtime, &data, 1).unwrap();
+        let old_id = runtime.image(&built["dev"].image).unwrap().id;
+
+        let changes = update_distrobox_assemble_data(&runtime, &data).unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            changes["dev"]
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>(),
+            ["bash: 5.1 -> 5.2"]
+        );
+        // the upgrade is a new layer on top of the release tag
+        let image = runtime.image(&built["dev"].image).unwrap();
+        assert_eq!(image.parent, Some(old_id));
+        assert_eq!(image.labels["status"], "package_upgrade");
+        assert!(runtime.container_names().is_empty());
+    }
+
+    #[test]
+    fn test_write_containerfiles() {
+        let runtime = FakeRuntime::new().with_image("ubuntu:latest", UBUNTU_OS_RELEASE);
+        let mut data = HashMap::new();
+        data.insert(
+            "dev1".to_string(),
+            create_test_container_assemble_data(&["git", "vim"]),
+        );
+        data.insert(
+            "dev2".to_string(),
+            create_test_container_assemble_data(&["git", "htop"]),
+        );
+        build_distrobox_assemble_data(&runtime, &data, 1).unwrap();
+        let dir = std::env::temp_dir().join(format!(
+            "distrobox_boost_test_containerfiles_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+
+        let paths = write_containerfiles(&runtime, &data, &dir).unwrap();
+
+        let names: Vec<String> = paths
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "ubuntu_latest.Containerfile",
+                "dev1.Containerfile",
+                "dev2.Containerfile"
+            ]
+        );
+        let parent = fs::read_to_string(&paths[0]).unwrap();
+        assert!(parent.contains("\nFROM ubuntu:latest\n"));
+        assert!(parent.contains("\nRUN [\"apt-get\",\"install\",\"-y\",\"git\"]\n"));
+        let dev1 = fs::read_to_string(&paths[1]).unwrap();
+        assert!(dev1.starts_with(&format!(
+            "# podman build -t {} -f dev1.Containerfile .\n",
+            get_release_image_name("dev1")
+        )));
+        assert!(dev1.contains(&format!(
+            "\nFROM {}\n",
+            get_release_image_name("ubuntu:latest")
+        )));
+        assert!(dev1.contains("\nRUN [\"apt-get\",\"install\",\"-y\",\"vim\"]\n"));
+        assert!(!dev1.contains("\"git\""));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_build_distrobox_assemble_data_dockerfile() {
+        let runtime = FakeRuntime::new().with_image("ubuntu:latest", UBUNTU_OS_RELEASE);
+        let dir = std::env::temp_dir().join(format!(
+            "distrobox_boost_test_converter_dockerfile_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dockerfile = dir.join("Containerfile");
+        fs::write(&dockerfile, "FROM ubuntu:latest\n").unwrap();
+        let mut data = HashMap::new();
+        data.insert(
+            "app".to_string(),
+            ContainerAssembleData {
+                image: format!("dockerfile://{}", dockerfile.display()),
+                dockerfile_build_args: Some(vec!["VERSION=1".to_string()]),
+                ..create_test_container_assemble_data(&["git"])
+            },
+        );
+
+        build_distrobox_assemble_data(&runtime, &data, 1).unwrap();
+        let paths = write_containerfiles(&runtime, &data, &dir).unwrap();
+
+        // the parent of "app" is named after the Dockerfile and its options
+        let build = runtime
+            .calls()
+            .into_iter()
+            .find(|call| call.starts_with("build "))
+            .unwrap();
+        let dockerfile_image = build.split(' ').nth(1).unwrap().to_string();
+        assert!(dockerfile_image.starts_with(&format!(
+            "{}/dockerfile/{}_containerfile",
+            get_distrobox_boost_image_prefix(),
+            dir.display().to_string()[1..].replace('/', "_")
+        )));
+        assert!(runtime.image(&dockerfile_image).is_some());
+        assert!(build.contains(" --build-arg VERSION=1 "));
+        let containerfile = fs::read_to_string(&paths[0]).unwrap();
+        assert!(containerfile.starts_with(&format!(
+            "# podman build -t {} -f {} --build-arg VERSION=1 {}\n",
+            dockerfile_image,
+            dockerfile.display(),
+            dir.display()
+        )));
+        assert!(containerfile.contains(&format!("\nFROM {}\n", dockerfile_image)));
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -4,378 +4,949 @@ mod distro;
 mod distrobox;
 mod distrobox_config_converter;
 mod oci;
+mod package_manifest;
 mod set_similarity;
 mod utils;
 
 use std::collections::HashMap;
 
 use crate::config::*;
+use crate::distro::definitions::{load_definitions, set_definitions};
+use crate::distro::package_diff::format_package_changes;
+use crate::distrobox::capabilities::probe_distrobox;
 use crate::distrobox::command_helper::*;
 use crate::distrobox::parser::assemble::{
-    assemble_distrobox_to_str, parse_distrobox_assemble, ContainerAssembleData,
+    assemble_distrobox_to_str, parse_distrobox_assemble, update_distrobox_assemble_document,
+    ContainerAssembleData,
 };
-use crate::distrobox_config_converter::build_distrobox_assemble_data;
-use crate::oci::command_helper::{
-    export_images, import_images, list_images_by_prefix, pin_image, unpin_image,
+use crate::distrobox::parser::validate::{validate_distrobox_assemble_files, Diagnostic};
+use crate::distrobox_config_converter::{
+    apply_distrobox_capabilities, build_distrobox_assemble_data, get_release_image_name,
+    update_distrobox_assemble_data, write_containerfiles,
 };
-use clap::Parser;
-
-fn build(
+use crate::oci::container_runtime::{new_container_runtime, ContainerRuntime};
+use crate::oci::gc::collect_garbage;
+use crate::oci::image_builder::diff_images;
+use crate::oci::pin::{get_pin_status, load_pins, pin_image, unpin_image, PinMode};
+use crate::package_manifest::{read_package_manifest, record_package_manifests};
+use crate::utils::ini::IniDocument;
+use clap::{ArgGroup, Parser, Subcommand};
+
+fn with_extra_packages(
     distrobox_assemble_data: &HashMap<String, ContainerAssembleData>,
-    extra_packages: Option<Vec<String>>,
+    extra_packages: &[String],
 ) -> HashMap<String, ContainerAssembleData> {
     let mut distrobox_assemble_data = distrobox_assemble_data.clone();
-    for value in distrobox_assemble_data.values_mut() {
-        if let Some(ref pkgs) = extra_packages {
+    if !extra_packages.is_empty() {
+        for value in distrobox_assemble_data.values_mut() {
             let packages = value.packages.get_or_insert(Vec::new());
-            packages.extend(pkgs.clone());
+            packages.extend_from_slice(extra_packages);
         }
     }
+    distrobox_assemble_data
+}
+
+fn build(
+    distrobox_assemble_data: &HashMap<String, ContainerAssembleData>,
+    extra_packages: &[String],
+    jobs: usize,
+) -> HashMap<String, ContainerAssembleData> {
+    let distrobox_assemble_data = with_extra_packages(distrobox_assemble_data, extra_packages);
+    let runtime = get_container_runtime();
     let new_distrobox_assemble_data =
-        build_distrobox_assemble_data(&get_container_manager(), &distrobox_assemble_data);
+        match build_distrobox_assemble_data(runtime.as_ref(), &distrobox_assemble_data, jobs) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Build failed:\n{}", e.stderr);
+                std::process::exit(1);
+            }
+        };
+    record_manifests(runtime.as_ref(), &new_distrobox_assemble_data);
     new_distrobox_assemble_data
 }
 
-#[derive(Parser, Debug)]
-#[clap(author, version, about)]
-struct Args {
-    #[clap(short = 'n', long)]
-    name: Option<String>,
+fn record_manifests(
+    runtime: &dyn ContainerRuntime,
+    distrobox_assemble_data: &HashMap<String, ContainerAssembleData>,
+) {
+    let manifest_dir = get_distrobox_boost_manifest_dir();
+    if let Err(e) = record_package_manifests(runtime, distrobox_assemble_data, &manifest_dir) {
+        eprintln!("Failed to record package manifests: {}", e);
+    }
+}
+
+fn emit_containerfiles(
+    distrobox_assemble_data: &HashMap<String, ContainerAssembleData>,
+    extra_packages: &[String],
+    dir: &str,
+) {
+    let runtime = get_container_runtime();
+    let distrobox_assemble_data = with_extra_packages(distrobox_assemble_data, extra_packages);
+    match write_containerfiles(
+        runtime.as_ref(),
+        &distrobox_assemble_data,
+        std::path::Path::new(dir),
+    ) {
+        Ok(paths) => {
+            for path in paths {
+                println!("Wrote {}", path.display());
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to write Containerfiles: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Contents of the input files, the program stops if one cannot be read
+fn read_input_contents(input_files: &[String]) -> Vec<(String, String)> {
+    input_files
+        .iter()
+        .map(|input_file| match std::fs::read_to_string(input_file) {
+            Ok(content) => (input_file.clone(), content),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", input_file, e);
+                std::process::exit(1);
+            }
+        })
+        .collect()
+}
+
+/// Print the problems of the files, the program stops if one of them is an error
+fn check_diagnostics(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+        std::process::exit(1);
+    }
+}
+
+fn read_input_files(input_files: &[String]) -> HashMap<String, ContainerAssembleData> {
+    let contents = read_input_contents(input_files);
+    let files: Vec<(&str, &str)> = contents
+        .iter()
+        .map(|(file, content)| (file.as_str(), content.as_str()))
+        .collect();
+    check_diagnostics(&validate_distrobox_assemble_files(&files));
+
+    let mut distrobox_assemble_data_map = HashMap::new();
+    for (_, content) in &contents {
+        match parse_distrobox_assemble(content) {
+            Ok(data) => distrobox_assemble_data_map.extend(data),
+            Err(diagnostics) => check_diagnostics(&diagnostics),
+        }
+    }
+    distrobox_assemble_data_map
+}
+
+fn doctor() {
+    match find_container_manager() {
+        Some(container_manager) => {
+            let runtime = new_container_runtime(&container_manager);
+            match runtime.info() {
+                Ok(info) => {
+                    println!("Container runtime: {} {}", runtime.name(), info.version);
+                    println!("Rootless: {}", if info.rootless { "yes" } else { "no" });
+                    println!("Storage driver: {}", info.storage_driver);
+                }
+                Err(e) => println!(
+                    "Container runtime: {} ({})",
+                    runtime.name(),
+                    e.stderr.trim()
+                ),
+            }
+        }
+        None => println!("Container runtime: not found, install podman or docker"),
+    }
+
+    let capabilities = probe_distrobox();
+    match &capabilities.version {
+        Some(version) => println!("distrobox: {}", version),
+        None => println!("distrobox: not found"),
+    }
+    match (&capabilities.init_path, &capabilities.setup_marker) {
+        (Some(init_path), Some(marker)) => {
+            println!(
+                "distrobox-init: {}, skips its setup when {} exists",
+                init_path.display(),
+                marker
+            )
+        }
+        (Some(init_path), None) => {
+            println!(
+                "distrobox-init: {}, checks no setup marker",
+                init_path.display()
+            )
+        }
+        (None, _) => println!("distrobox-init: not found"),
+    }
+    if let (_, Some(warning)) = capabilities.choose_setup_marker() {
+        println!("Warning: {}", warning);
+    }
+    if let (_, Some(warning)) = capabilities.choose_assemble_pull() {
+        println!("Warning: {}", warning);
+    }
+}
+
+fn validate(input_files: &[String]) {
+    let contents = read_input_contents(input_files);
+    let files: Vec<(&str, &str)> = contents
+        .iter()
+        .map(|(file, content)| (file.as_str(), content.as_str()))
+        .collect();
+    let diagnostics = validate_distrobox_assemble_files(&files);
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+    if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+        std::process::exit(1);
+    }
+    if diagnostics.is_empty() {
+        println!("{} file(s) OK", input_files.len());
+    }
+}
+
+/// The input files as one document, to write the build result back without losing
+/// comments and order
+fn read_input_document(input_files: &[String]) -> IniDocument {
+    let mut document = IniDocument::default();
+    for input_file in input_files {
+        match std::fs::read_to_string(input_file) {
+            Ok(content) => document.append(IniDocument::parse(&content)),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", input_file, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    document
+}
+
+/// A single container from the command line, `assemble` are extra ini lines like "init=true"
+fn single_container_data(
+    name: &str,
+    package: Option<&str>,
+    assemble: &[String],
+) -> HashMap<String, ContainerAssembleData> {
+    let mut assemble_data = HashMap::new();
+    assemble_data.insert(
+        name.to_string(),
+        ContainerAssembleData {
+            packages: package.map(|package| vec![package.to_string()]),
+            ..Default::default()
+        },
+    );
+    let mut assemble_content = assemble_distrobox_to_str(&assemble_data);
+    assemble_content.push_str(&assemble.join("\n"));
+    match parse_distrobox_assemble(&assemble_content) {
+        Ok(data) => data,
+        Err(diagnostics) => {
+            eprintln!("Invalid --assemble lines:");
+            for diagnostic in diagnostics {
+                eprintln!("  {}", diagnostic);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+fn update(input_files: &[String]) {
+    let mut distrobox_assemble_data_map = read_input_files(input_files);
+    let runtime = get_container_runtime();
+    let changes =
+        match update_distrobox_assemble_data(runtime.as_ref(), &distrobox_assemble_data_map) {
+            Ok(changes) => changes,
+            Err(e) => {
+                eprintln!("Update failed: {}", e);
+                std::process::exit(1);
+            }
+        };
+    for (name, package_changes) in changes {
+        println!("{}: {} package(s) changed", name, package_changes.len());
+        for change in package_changes {
+            println!("  {}", change);
+        }
+    }
+    for (name, value) in distrobox_assemble_data_map.iter_mut() {
+        value.image = get_release_image_name(name);
+    }
+    record_manifests(runtime.as_ref(), &distrobox_assemble_data_map);
+}
+
+fn diff(image_a: &str, image_b: &str, package_manager: &Option<String>, json: bool) {
+    let runtime = get_container_runtime();
+    let changes = match diff_images(runtime.as_ref(), image_a, image_b, package_manager) {
+        Ok(changes) => changes,
+        Err(e) => {
+            eprintln!("Diff failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&changes).unwrap());
+    } else {
+        print!("{}", format_package_changes(&changes));
+    }
+}
+
+fn print_packages(container: &str) {
+    let manifest_dir = get_distrobox_boost_manifest_dir();
+    let manifest = match read_package_manifest(&manifest_dir, container) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("No package manifest for {}: {}", container, e);
+            std::process::exit(1);
+        }
+    };
+    println!("# {} ({})", manifest.image, manifest.package_manager);
+    for package in manifest.packages {
+        println!("{} {}", package.name, package.version);
+    }
+}
 
-    #[clap(index = 1, allow_hyphen_values = true, value_terminator = "--")]
-    package_params: Option<Vec<String>>,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Build the images of distrobox-assemble containers and write the new ini
+    Build(BuildArgs),
+    /// Build a container for a package, create it and run the package inside
+    Run(RunArgs),
+    /// Export distrobox-boost images to a tar file
+    Export {
+        #[clap(value_name = "PATH")]
+        output: String,
+
+        /// Only export the images of these ini files
+        #[clap(short, long, num_args = 1..)]
+        input: Vec<String>,
+    },
+    /// Import images from a tar file
+    Import {
+        #[clap(value_name = "PATH")]
+        input: String,
+    },
+    /// Protect images from being pruned
+    #[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Pin {
+        #[clap(subcommand)]
+        command: Option<PinCommand>,
+
+        #[clap(flatten)]
+        args: PinArgs,
+
+        /// Keep a stopped container instead of a running one, `system prune` removes it
+        #[clap(long)]
+        stopped: bool,
+    },
+    /// Stop protecting images from being pruned
+    Unpin(PinArgs),
+    /// List distrobox-boost images
+    List,
+    /// Remove builder layers that no release or pinned image uses
+    Gc {
+        /// Only print the images that would be removed
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Keep layers updated in the last DAYS days
+        #[clap(long, value_name = "DAYS")]
+        keep_days: Option<u64>,
+    },
+    /// Upgrade all packages inside already built images
+    Update {
+        #[clap(short, long, num_args = 1.., required = true)]
+        input: Vec<String>,
+    },
+    /// Show packages added, removed and changed from one image to another
+    Diff {
+        image_a: String,
+        image_b: String,
+
+        /// Print the changes as JSON
+        #[clap(long)]
+        json: bool,
+
+        #[clap(long)]
+        package_manager: Option<String>,
+    },
+    /// Print the packages installed in the image of a built container
+    Packages { container: String },
+    /// Show the container runtime and distrobox that images are built for
+    Doctor,
+    /// Check distrobox-assemble ini files without building anything
+    Validate {
+        #[clap(short, long, num_args = 1.., required = true)]
+        input: Vec<String>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(group(ArgGroup::new("containers").required(true).multiple(true)))]
+struct BuildArgs {
+    /// distrobox-assemble ini files
+    #[clap(short, long, num_args = 1.., group = "containers")]
+    input: Vec<String>,
+
+    /// Build a single container with this name
+    #[clap(short, long, group = "containers")]
+    name: Option<String>,
 
-    #[clap(short, long, allow_hyphen_values = true, value_terminator = ",")]
-    assemble: Option<Vec<String>>,
+    /// Extra ini lines for the container given by --name, e.g. "init=true"
+    #[clap(short, long, requires = "name")]
+    assemble: Vec<String>,
 
+    /// Packages added to every container
     #[clap(short, long, num_args = 1..)]
-    input: Option<Vec<String>>,
+    pkg: Vec<String>,
 
+    /// Write the new ini to this file, it is printed if neither --output nor --output-dir is set
     #[clap(short, long)]
     output: Option<String>,
 
+    /// Write one ini per container to this directory
     #[clap(short = 'O', long)]
     output_dir: Option<String>,
 
-    #[arg(num_args(0..))]
+    #[clap(long)]
+    non_distrobox: bool,
+
+    /// Build up to N independent images at the same time
+    #[clap(short = 'j', long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+
+    /// Pin the built images
+    #[clap(long)]
+    pin: bool,
+
+    /// Also write a Containerfile reproducing each built image to this directory
+    #[clap(long, value_name = "DIR")]
+    emit_containerfile: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    /// Package to install and run
+    package: String,
+
+    /// Arguments for the package, after "--"
+    #[clap(last = true)]
+    params: Vec<String>,
+
+    /// Container name, defaults to the package
     #[clap(short, long)]
-    pkg: Option<Vec<String>>,
+    name: Option<String>,
+
+    /// Extra ini lines for the container, e.g. "init=true"
+    #[clap(short, long)]
+    assemble: Vec<String>,
+
+    /// Extra argument for `distrobox assemble create`, e.g. "--replace"
+    #[clap(long, allow_hyphen_values = true)]
+    assemble_arg: Vec<String>,
+
+    /// Extra argument for `distrobox enter`
+    #[clap(long, allow_hyphen_values = true)]
+    enter_arg: Vec<String>,
+
+    /// Packages installed together with the package
+    #[clap(short, long, num_args = 1..)]
+    pkg: Vec<String>,
+
+    /// Run this command instead of the package
+    #[clap(
+        short,
+        long,
+        num_args = 1..,
+        allow_hyphen_values = true,
+        value_terminator = "--",
+        conflicts_with = "params"
+    )]
+    run: Vec<String>,
 
     #[clap(long)]
     non_distrobox: bool,
-    #[clap(short = 'I', long)]
-    image_prefix: Option<String>,
 
+    /// Pin the built image
     #[clap(long)]
     pin: bool,
-    #[clap(long)]
-    unpin: bool,
+}
 
-    /// Export all distrobox-boost images to a tar file
-    #[clap(long, value_name = "PATH")]
-    export: Option<String>,
+#[derive(Subcommand, Debug)]
+enum PinCommand {
+    /// List pinned images
+    List,
+    /// Check that the containers protecting the pinned images still exist
+    Status,
+}
 
-    /// Import images from a tar file
-    #[clap(long, value_name = "PATH")]
-    import: Option<String>,
+#[derive(clap::Args, Debug)]
+#[clap(group(ArgGroup::new("targets").required(true).multiple(true)))]
+struct PinArgs {
+    /// Images to pin
+    #[clap(group = "targets")]
+    images: Vec<String>,
 
-    #[clap(long, allow_hyphen_values = true, value_terminator = ",")]
-    enter: Option<Vec<String>>,
+    /// Use the built images of the containers in these ini files
+    #[clap(short, long, num_args = 1.., group = "targets")]
+    input: Vec<String>,
+}
 
-    #[clap(long)]
-    no_run: bool,
+#[derive(Parser, Debug)]
+#[clap(author, version, about)]
+struct Cli {
+    /// Prefix of the images built by distrobox-boost
+    #[clap(short = 'I', long, global = true)]
+    image_prefix: Option<String>,
 
-    #[arg(num_args(0..))]
-    #[clap(short, long, allow_hyphen_values = true, value_terminator = "--")]
-    run: Option<Vec<String>>,
+    #[clap(subcommand)]
+    command: Command,
 }
 
-fn main() {
-    let args = Args::parse();
-    let mut package: Option<String> = None;
-    let mut package_params: Option<Vec<String>> = None;
-    if let Some(ref params) = args.package_params {
-        package = params.first().and_then(|s| Some(s.to_string()));
-        package_params = Some(
-            params[1..]
-                .iter()
-                .map(|param| {
-                    if param == "\\--" {
-                        "--".to_string()
-                    } else {
-                        param.clone()
-                    }
-                })
-                .collect(),
-        );
-    }
-    let name = if let Some(ref name) = args.name {
-        Some(name.to_string())
+fn build_command(args: &BuildArgs) {
+    if args.non_distrobox {
+        set_distrobox_mode(false);
+        println!("Non distrobox mode");
+    }
+    if get_distrobox_mode() {
+        apply_distrobox_capabilities(&probe_distrobox());
+    }
+    let mut distrobox_assemble_data_map = HashMap::new();
+    if let Some(ref name) = args.name {
+        distrobox_assemble_data_map.extend(single_container_data(name, None, &args.assemble));
+    }
+    distrobox_assemble_data_map.extend(read_input_files(&args.input));
+
+    let new_distrobox_assemble_data = build(&distrobox_assemble_data_map, &args.pkg, args.jobs);
+    let file_content = if args.input.is_empty() {
+        assemble_distrobox_to_str(&new_distrobox_assemble_data)
     } else {
-        package.clone()
+        let mut document = read_input_document(&args.input);
+        update_distrobox_assemble_document(&mut document, &new_distrobox_assemble_data);
+        document.to_string()
     };
-    // Handle export command early (independent operation)
-    if let Some(ref export_path) = args.export {
-        if let Some(ref image_prefix) = args.image_prefix {
-            set_distrobox_boost_image_prefix(image_prefix);
-        }
-
-        let images: Vec<String> = if let Some(ref input_files) = args.input {
-            // Export only images from specified ini files (without building)
-            let mut all_images = Vec::new();
-            for input_file in input_files {
-                let content = match std::fs::read_to_string(input_file) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        eprintln!("Failed to read {}: {}", input_file, e);
-                        std::process::exit(1);
-                    }
-                };
-                let assemble_data = parse_distrobox_assemble(&content);
-                for data in assemble_data.values() {
-                    if !all_images.contains(&data.image) {
-                        all_images.push(data.image.clone());
-                    }
-                }
-            }
-            println!("Exporting images from {} ini file(s)", input_files.len());
-            all_images
-        } else {
-            // Export all images with prefix
-            let prefix = get_distrobox_boost_image_prefix();
-            println!("Exporting images with prefix: {}", prefix);
-            match list_images_by_prefix(&get_container_manager(), &prefix) {
-                Ok(imgs) => imgs,
-                Err(e) => {
-                    eprintln!("Failed to list images: {}", e);
-                    std::process::exit(1);
-                }
-            }
-        };
 
-        if images.is_empty() {
-            eprintln!("No images found to export");
-            std::process::exit(1);
-        }
-        println!("Found {} images:", images.len());
-        for image in &images {
-            println!("  - {}", image);
-        }
-        match export_images(&get_container_manager(), &images, export_path) {
-            Ok(()) => {
-                println!("Successfully exported to {}", export_path);
-            }
-            Err(e) => {
-                eprintln!("Export failed: {}", e);
-                std::process::exit(1);
-            }
-        }
-        return;
+    if let Some(ref output_path) = args.output {
+        std::fs::write(output_path, &file_content).unwrap();
     }
-
-    // Handle import command (independent operation)
-    if let Some(ref import_path) = args.import {
-        println!("Importing images from: {}", import_path);
-
-        match import_images(&get_container_manager(), import_path) {
-            Ok(output) => {
-                println!("Successfully imported images");
-                if !output.is_empty() {
-                    println!("{}", output.trim());
-                }
-            }
-            Err(e) => {
-                eprintln!("Import failed: {}", e);
-                std::process::exit(1);
-            }
+    if let Some(ref output_dir) = args.output_dir {
+        let output_dir_path = std::path::PathBuf::from(output_dir);
+        for (name, value) in &new_distrobox_assemble_data {
+            let mut output_path = output_dir_path.clone();
+            output_path.push(name);
+            output_path.set_extension("ini");
+            let data = HashMap::from([(name.clone(), value.clone())]);
+            std::fs::write(&output_path, assemble_distrobox_to_str(&data)).unwrap();
         }
-        return;
+    }
+    if args.output.is_none() && args.output_dir.is_none() {
+        println!("{}", file_content);
     }
 
-    if name.is_none() && args.input.is_none() && args.assemble.is_none() {
-        println!("Use --help to get help");
-        return;
+    if args.pin {
+        pin(&images_of(&new_distrobox_assemble_data), PinMode::Running);
     }
-    println!("package: {:?}", package);
-    println!("package_params: {:?}", package_params);
-    println!("pkgs: {:?}", args.pkg);
+    if let Some(ref dir) = args.emit_containerfile {
+        emit_containerfiles(&distrobox_assemble_data_map, &args.pkg, dir);
+    }
+}
 
-    let run_args = match args.run {
-        Some(run) => Some(
-            run.iter()
-                .map(|param| {
-                    if param == "\\--" {
-                        "--".to_string()
-                    } else {
-                        param.clone()
-                    }
-                })
-                .collect::<Vec<_>>(),
-        ),
-        None => None,
-    };
+fn run(args: &RunArgs) {
     if args.non_distrobox {
         set_distrobox_mode(false);
         println!("Non distrobox mode");
     }
-    if let Some(ref image_prefix) = args.image_prefix {
-        set_distrobox_boost_image_prefix(image_prefix);
+    if get_distrobox_mode() {
+        apply_distrobox_capabilities(&probe_distrobox());
+    }
+    let name = args.name.clone().unwrap_or(args.package.clone());
+    let distrobox_assemble_data_map =
+        single_container_data(&name, Some(&args.package), &args.assemble);
+    let new_distrobox_assemble_data = build(&distrobox_assemble_data_map, &args.pkg, 1);
+    if args.pin {
+        pin(&images_of(&new_distrobox_assemble_data), PinMode::Running);
     }
 
-    let mut distrobox_assemble_data_map = HashMap::new();
-    if let Some(ref name) = name {
-        let mut assemble_data = HashMap::new();
-        let packages = if let Some(ref package) = package {
-            Some(vec![package.to_string()])
-        } else {
-            None
-        };
-        assemble_data.insert(
-            name.clone(),
-            ContainerAssembleData {
-                packages,
-                ..Default::default()
-            },
-        );
-        let mut assemble_content = assemble_distrobox_to_str(&assemble_data);
-        if let Some(ref assemple_data) = args.assemble {
-            assemble_content.push_str(
-                &assemple_data
-                    .iter()
-                    .filter(|line| !line.starts_with("-"))
-                    .cloned()
-                    .collect::<Vec<_>>()
-                    .join("\n"),
-            );
+    let assemble_file_path = format!(".distrobox_assemble_{}.tmp.ini", name);
+    std::fs::write(
+        &assemble_file_path,
+        assemble_distrobox_to_str(&new_distrobox_assemble_data),
+    )
+    .unwrap();
+    let result = distrobox_assemble(
+        &assemble_file_path,
+        "create",
+        &args
+            .assemble_arg
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<&str>>(),
+        true,
+    );
+    std::fs::remove_file(&assemble_file_path).unwrap();
+    result.unwrap();
+
+    let cmds = if !args.run.is_empty() {
+        args.run.clone()
+    } else {
+        let mut cmds = vec![args.package.clone()];
+        cmds.extend(args.params.clone());
+        cmds
+    };
+    let _ = distrobox_enter(
+        &name,
+        &args
+            .enter_arg
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<&str>>(),
+        &cmds.iter().map(|s| s.as_str()).collect::<Vec<&str>>(),
+    )
+    .unwrap();
+}
+
+fn export(output_path: &str, input_files: &[String]) {
+    let runtime = get_container_runtime();
+    let images: Vec<String> = if !input_files.is_empty() {
+        // Export only images from specified ini files (without building)
+        let mut all_images = Vec::new();
+        for data in read_input_files(input_files).values() {
+            if !all_images.contains(&data.image) {
+                all_images.push(data.image.clone());
+            }
+        }
+        println!("Exporting images from {} ini file(s)", input_files.len());
+        all_images
+    } else {
+        // Export all images with prefix
+        let prefix = get_distrobox_boost_image_prefix();
+        println!("Exporting images with prefix: {}", prefix);
+        match runtime.list_images_by_prefix(&prefix) {
+            Ok(imgs) => imgs,
+            Err(e) => {
+                eprintln!("Failed to list images: {}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    if images.is_empty() {
+        eprintln!("No images found to export");
+        std::process::exit(1);
+    }
+    println!("Found {} images:", images.len());
+    for image in &images {
+        println!("  - {}", image);
+    }
+    match runtime.export_images(&images, output_path) {
+        Ok(()) => {
+            println!("Successfully exported to {}", output_path);
+        }
+        Err(e) => {
+            eprintln!("Export failed: {}", e);
+            std::process::exit(1);
         }
-        distrobox_assemble_data_map.extend(parse_distrobox_assemble(&assemble_content));
     }
+}
 
-    if let Some(ref inputs) = args.input {
-        for input in inputs {
-            let content = std::fs::read_to_string(input).unwrap();
-            distrobox_assemble_data_map.extend(parse_distrobox_assemble(&content));
+fn import(input_path: &str) {
+    println!("Importing images from: {}", input_path);
+    match get_container_runtime().import_images(input_path) {
+        Ok(output) => {
+            println!("Successfully imported images");
+            if !output.is_empty() {
+                println!("{}", output.trim());
+            }
+        }
+        Err(e) => {
+            eprintln!("Import failed: {}", e);
+            std::process::exit(1);
         }
     }
+}
 
-    let new_distrobox_assemble_data = build(&distrobox_assemble_data_map, args.pkg);
-    let file_content = assemble_distrobox_to_str(&new_distrobox_assemble_data);
+fn images_of(distrobox_assemble_data: &HashMap<String, ContainerAssembleData>) -> Vec<String> {
+    distrobox_assemble_data
+        .values()
+        .map(|data| data.image.clone())
+        .collect()
+}
+
+/// Images given on the command line and the built images of the containers in the ini files
+fn pin_targets(args: &PinArgs) -> Vec<String> {
+    let mut images = args.images.clone();
+    images.extend(
+        read_input_files(&args.input)
+            .keys()
+            .map(|name| get_release_image_name(name)),
+    );
+    images
+}
 
-    if args.no_run {
-        if args.output.is_none() && args.output_dir.is_none() {
-            println!("{}", file_content);
-            return;
+fn pin(images: &[String], mode: PinMode) {
+    let runtime = get_container_runtime();
+    let state_path = get_distrobox_boost_pin_state_path();
+    for image in images {
+        match pin_image(runtime.as_ref(), &state_path, image, mode) {
+            Ok(entry) => println!("Pinned {} by {}", image, entry.container),
+            Err(e) => println!("Pin image {} failed: {}", image, e),
         }
     }
+}
 
-    let mut file_path_map = HashMap::new();
+fn unpin(images: &[String]) {
+    let runtime = get_container_runtime();
+    let state_path = get_distrobox_boost_pin_state_path();
+    for image in images {
+        match unpin_image(runtime.as_ref(), &state_path, image) {
+            Ok(true) => println!("Unpinned {}", image),
+            Ok(false) => println!("{} is not pinned", image),
+            Err(e) => println!("Unpin image {} failed: {}", image, e),
+        }
+    }
+}
 
-    if let Some(output_path) = args.output {
-        std::fs::write(&output_path, &file_content).unwrap();
-        for name in new_distrobox_assemble_data.keys() {
-            file_path_map.insert(name.to_string(), output_path.clone());
+fn list_pins() {
+    match load_pins(&get_distrobox_boost_pin_state_path()) {
+        Ok(pins) => {
+            for entry in pins {
+                println!("{} ({:?}, {})", entry.image, entry.mode, entry.container);
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to read pins: {}", e);
+            std::process::exit(1);
         }
     }
-    if let Some(output_dir) = args.output_dir {
-        let output_dir_path = std::path::PathBuf::from(output_dir);
-        for name in new_distrobox_assemble_data.keys() {
-            let mut output_path = output_dir_path.clone();
-            output_path.push(name);
-            output_path.set_extension("ini");
-            let mut data = HashMap::new();
-            data.insert(
-                name.clone(),
-                new_distrobox_assemble_data.get(name).unwrap().clone(),
-            );
-            let file_content = assemble_distrobox_to_str(&data);
-            std::fs::write(&output_path, &file_content).unwrap();
-            file_path_map.insert(name.to_string(), output_path.to_str().unwrap().to_string());
-        }
-    }
-
-    if args.unpin {
-        for data in new_distrobox_assemble_data.values() {
-            let result = unpin_image(&get_container_manager(), &data.image);
-            if let Err(e) = result {
-                println!("Unpin image {} failed: {}", &data.image, e);
+}
+
+fn pin_status() {
+    let runtime = get_container_runtime();
+    match get_pin_status(runtime.as_ref(), &get_distrobox_boost_pin_state_path()) {
+        Ok(status) => {
+            for (entry, exists) in status {
+                if exists {
+                    println!("{}: pinned", entry.image);
+                } else {
+                    println!(
+                        "{}: container {} is missing, pin it again",
+                        entry.image, entry.container
+                    );
+                }
             }
         }
+        Err(e) => {
+            eprintln!("Failed to check pins: {}", e);
+            std::process::exit(1);
+        }
     }
-    if args.pin {
-        for data in new_distrobox_assemble_data.values() {
-            let result = pin_image(&get_container_manager(), &data.image);
-            if let Err(e) = result {
-                println!("Pin image {} failed: {}", &data.image, e);
+}
+
+fn list() {
+    let runtime = get_container_runtime();
+    match runtime.list_images_by_prefix(&get_distrobox_boost_image_prefix()) {
+        Ok(images) => {
+            for image in images {
+                println!("{}", image);
             }
         }
+        Err(e) => {
+            eprintln!("Failed to list images: {}", e);
+            std::process::exit(1);
+        }
     }
+}
 
-    if !args.no_run {
-        if let Some(ref package) = package {
-            let mut tmp_assemble_file: Option<String> = None;
-            let assemble_file_path = if let Some(path) = file_path_map.get(package) {
-                path.clone()
-            } else {
-                let assemble_file_path = format!(".distrobox_assemble_{}.tmp.ini", package);
-                tmp_assemble_file = Some(assemble_file_path.clone());
-                std::fs::write(&assemble_file_path, &file_content).unwrap();
-                assemble_file_path
-            };
-            let assemble_args = if let Some(ref assemble_args) = args.assemble {
-                let assemble_args = &assemble_args
-                    .iter()
-                    .filter(|line| line.starts_with("-"))
-                    .cloned()
-                    .collect::<Vec<_>>();
-                if !assemble_args.contains(&"rm".to_string())
-                    && !assemble_args.contains(&"create".to_string())
-                {
-                    assemble_args.clone()
+fn gc(keep_days: Option<u64>, dry_run: bool) {
+    let runtime = get_container_runtime();
+    let prefix = get_distrobox_boost_image_prefix();
+    let pinned_images: Vec<String> = match load_pins(&get_distrobox_boost_pin_state_path()) {
+        Ok(pins) => pins.into_iter().map(|entry| entry.image).collect(),
+        Err(e) => {
+            eprintln!("Failed to read pins: {}", e);
+            std::process::exit(1);
+        }
+    };
+    match collect_garbage(
+        runtime.as_ref(),
+        &prefix,
+        &pinned_images,
+        keep_days,
+        dry_run,
+    ) {
+        Ok(images) => {
+            for image in &images {
+                if dry_run {
+                    println!("Would remove {}", image);
                 } else {
-                    let mut args = vec!["create".to_string()];
-                    args.extend(assemble_args.clone());
-                    args
+                    println!("Removed {}", image);
                 }
-            } else {
-                vec!["create".to_string()]
-            };
-
-            distrobox_assemble(
-                &assemble_file_path,
-                &assemble_args.first().unwrap(),
-                &assemble_args[1..]
-                    .iter()
-                    .map(|s| s.as_str())
-                    .collect::<Vec<&str>>(),
-                true,
-            )
-            .unwrap();
-            if let Some(tmp_assemble_file) = tmp_assemble_file {
-                std::fs::remove_file(&tmp_assemble_file).unwrap();
             }
+            if images.is_empty() {
+                println!("Nothing to remove under {}", prefix);
+            }
+        }
+        Err(e) => {
+            eprintln!("Garbage collection failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-            let enter_args = if let Some(enter_args) = args.enter {
-                enter_args
+fn main() {
+    let cli = Cli::parse();
+    match load_definitions(&get_distrobox_boost_config_dir()) {
+        Ok(definitions) => set_definitions(definitions),
+        Err(e) => {
+            eprintln!("Invalid package manager definitions: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(ref image_prefix) = cli.image_prefix {
+        set_distrobox_boost_image_prefix(image_prefix);
+    }
+    match cli.command {
+        Command::Build(ref args) => build_command(args),
+        Command::Run(ref args) => run(args),
+        Command::Export {
+            ref output,
+            ref input,
+        } => export(output, input),
+        Command::Import { ref input } => import(input),
+        Command::Pin {
+            command: Some(PinCommand::List),
+            ..
+        } => list_pins(),
+        Command::Pin {
+            command: Some(PinCommand::Status),
+            ..
+        } => pin_status(),
+        Command::Pin {
+            command: None,
+            ref args,
+            stopped,
+        } => pin(
+            &pin_targets(args),
+            if stopped {
+                PinMode::Stopped
             } else {
-                vec![]
-            };
+                PinMode::Running
+            },
+        ),
+        Command::Unpin(ref args) => unpin(&pin_targets(args)),
+        Command::List => list(),
+        Command::Gc { dry_run, keep_days } => gc(keep_days, dry_run),
+        Command::Update { ref input } => update(input),
+        Command::Diff {
+            ref image_a,
+            ref image_b,
+            json,
+            ref package_manager,
+        } => diff(image_a, image_b, package_manager, json),
+        Command::Packages { ref container } => print_packages(container),
+        Command::Validate { ref input } => validate(input),
+        Command::Doctor => doctor(),
+    }
+}
 
-            let cmds = if let Some(run_args) = run_args {
-                run_args.clone()
-            } else {
-                let mut cmds = vec![package.to_string()];
-                if let Some(ref args) = package_params {
-                    cmds.extend(args.clone());
-                }
-                cmds
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
 
-            let _ = distrobox_enter(
-                &package,
-                &enter_args.iter().map(|s| s.as_str()).collect::<Vec<&str>>(),
-                &cmds.iter().map(|s| s.as_str()).collect::<Vec<&str>>(),
-            )
-            .unwrap();
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli =
+            Cli::try_parse_from(["distrobox-boost", "run", "fish", "--", "-c", "ls -la"]).unwrap();
+        let Command::Run(args) = cli.command else {
+            panic!("not a run command");
+        };
+        assert_eq!(args.package, "fish");
+        assert_eq!(args.params, ["-c", "ls -la"]);
+
+        let cli = Cli::try_parse_from([
+            "distrobox-boost",
+            "run",
+            "fish",
+            "--run",
+            "bash",
+            "-c",
+            "ls -la",
+        ])
+        .unwrap();
+        let Command::Run(args) = cli.command else {
+            panic!("not a run command");
+        };
+        assert_eq!(args.run, ["bash", "-c", "ls -la"]);
+
+        let cli = Cli::try_parse_from([
+            "distrobox-boost",
+            "build",
+            "-i",
+            "example.ini",
+            "-I",
+            "my-prefix",
+            "-j",
+            "4",
+            "--emit-containerfile",
+            "out",
+        ])
+        .unwrap();
+        assert_eq!(cli.image_prefix.as_deref(), Some("my-prefix"));
+        let Command::Build(args) = cli.command else {
+            panic!("not a build command");
+        };
+        assert_eq!(args.input, ["example.ini"]);
+        assert_eq!(args.jobs, 4);
+        assert_eq!(args.emit_containerfile.as_deref(), Some("out"));
+
+        let cli = Cli::try_parse_from(["distrobox-boost", "doctor"]).unwrap();
+        assert!(matches!(cli.command, Command::Doctor));
+
+        let cli = Cli::try_parse_from(["distrobox-boost", "pin", "list"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Pin {
+                command: Some(PinCommand::List),
+                ..
+            }
+        ));
+        let cli = Cli::try_parse_from(["distrobox-boost", "pin", "--stopped", "ubuntu"]).unwrap();
+        let Command::Pin {
+            command: None,
+            args,
+            stopped: true,
+        } = cli.command
+        else {
+            panic!("not a stopped pin");
+        };
+        assert_eq!(args.images, ["ubuntu"]);
+    }
+
+    #[test]
+    fn test_cli_conflicts() {
+        for args in [
+            vec!["distrobox-boost", "build"],
+            vec!["distrobox-boost", "build", "-i", "a.ini", "-a", "init=true"],
+            vec!["distrobox-boost", "pin"],
+            vec!["distrobox-boost", "pin", "list", "ubuntu"],
+            vec!["distrobox-boost", "update"],
+            vec!["distrobox-boost", "export"],
+            vec!["distrobox-boost", "validate"],
+            vec![
+                "distrobox-boost",
+                "run",
+                "fish",
+                "--run",
+                "bash",
+                "--",
+                "-l",
+            ],
+            vec!["distrobox-boost", "--pin"],
+        ] {
+            assert!(Cli::try_parse_from(&args).is_err(), "{:?}", args);
         }
     }
 }
@@ -1,2 +1,10 @@
+pub mod cli_runtime;
 pub mod command_helper;
+pub mod container_command;
+pub mod container_runtime;
+pub mod containerfile;
+#[cfg(test)]
+pub mod fake_runtime;
+pub mod gc;
 pub mod image_builder;
+pub mod pin;
@@ -0,0 +1,176 @@
+// ContainerRuntime for engines driven through their command line, podman and docker
+// take the same arguments except for the few their flavor decides
+
+use crate::oci::command_helper;
+use crate::oci::container_command::ContainerCommand;
+use crate::oci::container_runtime::{ContainerRuntime, RuntimeInfo};
+use crate::utils::command_helper::{CommandError, CommandOutput};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CliFlavor {
+    Podman,
+    Docker,
+}
+
+impl CliFlavor {
+    /// Flavor of `command`, anything that is not docker is taken for podman
+    pub fn detect(command: &str) -> Self {
+        let executable = command.rsplit_once('/').map_or(command, |(_, name)| name);
+        if executable.starts_with("docker") {
+            CliFlavor::Docker
+        } else {
+            CliFlavor::Podman
+        }
+    }
+
+    fn export_images(
+        self,
+        command: &str,
+        images: &[String],
+        output_path: &str,
+    ) -> Result<(), CommandError> {
+        match self {
+            // podman only writes a multi-image archive with -m
+            CliFlavor::Podman => {
+                command_helper::export_images_with_args(command, images, output_path, &["-m"])
+            }
+            // docker save always writes a multi-image archive
+            CliFlavor::Docker => command_helper::export_images(command, images, output_path),
+        }
+    }
+
+    fn info(self, command: &str) -> Result<RuntimeInfo, CommandError> {
+        match self {
+            CliFlavor::Podman => {
+                let info = command_helper::get_info_json(command, "json")?;
+                Ok(command_helper::parse_podman_info(&info))
+            }
+            CliFlavor::Docker => {
+                let info = command_helper::get_info_json(command, "{{json .}}")?;
+                Ok(command_helper::parse_docker_info(&info))
+            }
+        }
+    }
+}
+
+pub struct CliRuntime {
+    command: String,
+    flavor: CliFlavor,
+}
+
+impl CliRuntime {
+    pub fn new(command: &str, flavor: CliFlavor) -> Self {
+        Self {
+            command: command.to_string(),
+            flavor,
+        }
+    }
+}
+
+impl ContainerRuntime for CliRuntime {
+    fn name(&self) -> &str {
+        &self.command
+    }
+
+    fn run_container(
+        &self,
+        name: &str,
+        image_name: &str,
+        cmd: &ContainerCommand,
+        realtime_output: bool,
+    ) -> Result<CommandOutput, CommandError> {
+        command_helper::run_container(&self.command, name, image_name, cmd, realtime_output)
+    }
+
+    fn run_container_with_args(
+        &self,
+        name: &str,
+        image_name: &str,
+        cmd: &ContainerCommand,
+        extra_args: &[&str],
+        realtime_output: bool,
+    ) -> Result<CommandOutput, CommandError> {
+        command_helper::run_container_with_args(
+            &self.command,
+            name,
+            image_name,
+            cmd,
+            extra_args,
+            realtime_output,
+        )
+    }
+
+    fn stop_container_with_args(
+        &self,
+        name: &str,
+        extra_args: &[&str],
+    ) -> Result<CommandOutput, CommandError> {
+        command_helper::stop_container_with_args(&self.command, name, extra_args)
+    }
+
+    fn remove_container(&self, name: &str) -> Result<CommandOutput, CommandError> {
+        command_helper::remove_container(&self.command, name)
+    }
+
+    fn check_container_exists(&self, container_name: &str) -> Result<bool, CommandError> {
+        command_helper::check_container_exists(&self.command, container_name)
+    }
+
+    fn commit_container(
+        &self,
+        container_name: &str,
+        image_name: &str,
+        instructions: &[&str],
+    ) -> Result<CommandOutput, CommandError> {
+        command_helper::commit_container(&self.command, container_name, image_name, instructions)
+    }
+
+    fn remove_image(&self, name: &str) -> Result<CommandOutput, CommandError> {
+        command_helper::remove_image(&self.command, name)
+    }
+
+    fn find_images(&self, filters: &[&str]) -> Result<Vec<String>, CommandError> {
+        command_helper::find_images(&self.command, filters)
+    }
+
+    fn tag_image(&self, name: &str, new_name: &str) -> Result<CommandOutput, CommandError> {
+        command_helper::tag_image(&self.command, name, new_name)
+    }
+
+    fn inspect_image(&self, image_name: &str, format: &str) -> Result<String, CommandError> {
+        command_helper::inspect_image(&self.command, image_name, format)
+    }
+
+    fn build_image_from_dockerfile_with_args(
+        &self,
+        image_name: &str,
+        dockerfile_path: &str,
+        context: &str,
+        extra_args: &[&str],
+    ) -> Result<CommandOutput, CommandError> {
+        command_helper::build_image_from_dockerfile_with_args(
+            &self.command,
+            image_name,
+            dockerfile_path,
+            context,
+            extra_args,
+        )
+    }
+
+    fn list_images_by_prefix(&self, prefix: &str) -> Result<Vec<String>, CommandError> {
+        command_helper::list_images_by_prefix(&self.command, prefix)
+    }
+
+    fn export_images(&self, images: &[String], output_path: &str) -> Result<(), CommandError> {
+        self.flavor
+            .export_images(&self.command, images, output_path)
+    }
+
+    fn import_images(&self, input_path: &str) -> Result<String, CommandError> {
+        command_helper::import_images(&self.command, input_path)
+    }
+
+    fn info(&self) -> Result<RuntimeInfo, CommandError> {
+        self.flavor.info(&self.command)
+    }
+}
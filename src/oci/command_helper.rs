@@ -1,5 +1,7 @@
 // run external progamm such as &get_container_manager() "docker"
 
+use crate::oci::container_command::ContainerCommand;
+use crate::oci::container_runtime::RuntimeInfo;
 use crate::utils::command_helper::*;
 use std::collections::HashSet;
 
@@ -7,7 +9,7 @@ pub fn run_container(
     container_runner: &str,
     name: &str,
     image_name: &str,
-    cmd: &str,
+    cmd: &ContainerCommand,
     realtime_output: bool,
 ) -> Result<CommandOutput, CommandError> {
     let mut args = vec![];
@@ -28,7 +30,7 @@ pub fn run_container_with_args(
     container_runner: &str,
     name: &str,
     image_name: &str,
-    cmd: &str,
+    cmd: &ContainerCommand,
     extra_args: &[&str],
     realtime_output: bool,
 ) -> Result<CommandOutput, CommandError> {
@@ -37,12 +39,12 @@ pub fn run_container_with_args(
         args.extend_from_slice(&["--name", name]);
     }
     args.extend_from_slice(extra_args);
-    if !cmd.is_empty() {
-        println!("Using sh -c to run command: {}", cmd);
-        args.extend_from_slice(&["--entrypoint", "sh", image_name, "-c", cmd]);
-    } else {
-        args.push(image_name);
+    match cmd {
+        ContainerCommand::Shell(script) => println!("Using sh -c to run command: {}", script),
+        ContainerCommand::Exec(argv) if !argv.is_empty() => println!("Running command: {}", cmd),
+        _ => {}
     }
+    args.extend(cmd.run_args(image_name));
 
     let output = run_command(container_runner, &args, realtime_output)?;
     Ok(output)
@@ -136,33 +138,16 @@ pub fn tag_image(
     Ok(output)
 }
 
-pub fn pin_image(container_runner: &str, image_name: &str) -> Result<String, CommandError> {
-    let name = format!("pin-{}", image_name.replace(":", "-").replace("/", "-"));
-    run_container_with_args(
-        container_runner,
-        &name,
-        image_name,
-        "tail -f /dev/null",
-        &["-d", "--restart", "unless-stopped"],
-        false,
-    )?;
-    Ok(name)
-}
-
-pub fn unpin_image(container_runner: &str, image_name: &str) -> Result<String, CommandError> {
-    let name = format!("pin-{}", image_name.replace(":", "-").replace("/", "-"));
-    let _ = stop_container_with_args(container_runner, &name, &["-t", "0"]);
-    remove_container(container_runner, &name)?;
-    Ok(name)
-}
-
-pub fn build_image_from_dockerfile_simple(
+pub fn build_image_from_dockerfile_with_args(
     container_runner: &str,
     image_name: &str,
     dockerfile_path: &str,
     context: &str,
+    extra_args: &[&str],
 ) -> Result<CommandOutput, CommandError> {
-    let args = vec!["build", "-t", image_name, "-f", dockerfile_path, context];
+    let mut args = vec!["build", "-t", image_name, "-f", dockerfile_path];
+    args.extend_from_slice(extra_args);
+    args.push(context);
     let output = run_command(container_runner, &args, true)?;
     Ok(output)
 }
@@ -186,11 +171,15 @@ pub fn list_images_by_prefix(
     container_runner: &str,
     prefix: &str,
 ) -> Result<Vec<String>, CommandError> {
+    // "*" does not match "/", images are named like <prefix>/release/<name>
     let filter = format!("reference={}/*", prefix);
+    let nested_filter = format!("reference={}/*/*", prefix);
     let args = vec![
         "images",
         "--filter",
         &filter,
+        "--filter",
+        &nested_filter,
         "--format",
         "{{.Repository}}:{{.Tag}}",
     ];
@@ -210,6 +199,15 @@ pub fn export_images(
     container_runner: &str,
     images: &[String],
     output_path: &str,
+) -> Result<(), CommandError> {
+    export_images_with_args(container_runner, images, output_path, &[])
+}
+
+pub fn export_images_with_args(
+    container_runner: &str,
+    images: &[String],
+    output_path: &str,
+    extra_args: &[&str],
 ) -> Result<(), CommandError> {
     if images.is_empty() {
         return Err(CommandError {
@@ -220,11 +218,8 @@ pub fn export_images(
         });
     }
 
-    // Build save command: podman uses -m for multi-image, docker doesn't need it
     let mut args: Vec<&str> = vec!["save"];
-    if container_runner.contains("podman") {
-        args.push("-m");
-    }
+    args.extend_from_slice(extra_args);
     args.push("-o");
     args.push(output_path);
     for image in images {
@@ -235,10 +230,7 @@ pub fn export_images(
     Ok(())
 }
 
-pub fn import_images(
-    container_runner: &str,
-    input_path: &str,
-) -> Result<String, CommandError> {
+pub fn import_images(container_runner: &str, input_path: &str) -> Result<String, CommandError> {
     if !std::path::Path::new(input_path).exists() {
         return Err(CommandError {
             stdout: String::new(),
@@ -253,6 +245,53 @@ pub fn import_images(
     Ok(output.stdout)
 }
 
+/// Output of `info --format <format>` parsed as JSON
+pub fn get_info_json(
+    container_runner: &str,
+    format: &str,
+) -> Result<serde_json::Value, CommandError> {
+    let args = vec!["info", "--format", format];
+    let output = run_command(container_runner, &args, false)?;
+    serde_json::from_str(&output.stdout).map_err(|e| CommandError {
+        stdout: output.stdout.clone(),
+        stderr: format!("Failed to parse {} info: {}", container_runner, e),
+        status: Some(1),
+        inner: None,
+    })
+}
+
+/// `podman info --format json`
+pub fn parse_podman_info(info: &serde_json::Value) -> RuntimeInfo {
+    RuntimeInfo {
+        version: info["version"]["Version"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        rootless: info["host"]["security"]["rootless"]
+            .as_bool()
+            .unwrap_or_default(),
+        storage_driver: info["store"]["graphDriverName"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+/// `docker info --format '{{json .}}'`
+pub fn parse_docker_info(info: &serde_json::Value) -> RuntimeInfo {
+    RuntimeInfo {
+        version: info["ServerVersion"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        rootless: info["SecurityOptions"]
+            .as_array()
+            .map(|options| options.iter().any(|option| option == "name=rootless"))
+            .unwrap_or_default(),
+        storage_driver: info["Driver"].as_str().unwrap_or_default().to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -260,6 +299,36 @@ mod tests {
     use std::env;
 
     #[test]
+    fn test_parse_runtime_info() {
+        let podman: serde_json::Value = serde_json::from_str(
+            r#"{"host":{"security":{"rootless":true}},"store":{"graphDriverName":"overlay"},"version":{"Version":"4.9.3"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            parse_podman_info(&podman),
+            RuntimeInfo {
+                version: "4.9.3".to_string(),
+                rootless: true,
+                storage_driver: "overlay".to_string(),
+            }
+        );
+
+        let docker: serde_json::Value = serde_json::from_str(
+            r#"{"ServerVersion":"24.0.7","Driver":"overlay2","SecurityOptions":["name=seccomp,profile=builtin","name=rootless"]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            parse_docker_info(&docker),
+            RuntimeInfo {
+                version: "24.0.7".to_string(),
+                rootless: true,
+                storage_driver: "overlay2".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_valid_command() {
         let container_runner = &get_container_manager();
         let name = "test_case_1";
@@ -267,18 +336,31 @@ mod tests {
         let cmd = "ls";
 
         let _ = remove_container(container_runner, name);
-        let result = run_container(container_runner, name, image_name, cmd, true);
+        let result = run_container(
+            container_runner,
+            name,
+            image_name,
+            &ContainerCommand::shell(cmd),
+            true,
+        );
         let _ = remove_container(container_runner, name);
         assert!(result.is_ok());
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_run_container_with_empty_name() {
         let container_runner = &get_container_manager();
         let image_name = "ubuntu";
         let cmd = "echo 'Hello, World!'";
 
-        let result = run_container(container_runner, "", image_name, cmd, true);
+        let result = run_container(
+            container_runner,
+            "",
+            image_name,
+            &ContainerCommand::shell(cmd),
+            true,
+        );
         assert!(
             result.is_ok(),
             "Expected the container to run successfully with an empty name."
@@ -293,6 +375,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_valid_command_with_args() {
         let container_runner = &get_container_manager();
         let name = "test_case_2";
@@ -300,25 +383,33 @@ mod tests {
         let cmd = "ls -la /";
 
         let _ = remove_container(container_runner, name);
-        let result = run_container(container_runner, name, image_name, cmd, true);
+        let result = run_container(
+            container_runner,
+            name,
+            image_name,
+            &ContainerCommand::shell(cmd),
+            true,
+        );
         let _ = remove_container(container_runner, name);
         assert!(result.is_ok());
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_empty_command() {
         let container_runner = &get_container_manager();
         let name = "test_case_3";
         let image_name = "ubuntu";
-        let cmd = "";
+        let cmd = ContainerCommand::default();
 
         let _ = remove_container(container_runner, name);
-        let result = run_container(container_runner, name, image_name, cmd, true);
+        let result = run_container(container_runner, name, image_name, &cmd, true);
         let _ = remove_container(container_runner, name);
         assert!(result.is_ok());
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_invalid_command() {
         let container_runner = &get_container_manager();
         let name = "test_case_4";
@@ -326,22 +417,37 @@ mod tests {
         let cmd = "non_existent_command";
 
         let _ = remove_container(container_runner, name);
-        let result = run_container(container_runner, name, image_name, cmd, true);
+        let result = run_container(
+            container_runner,
+            name,
+            image_name,
+            &ContainerCommand::shell(cmd),
+            true,
+        );
         let _ = remove_container(container_runner, name);
         assert!(result.is_err());
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_invalid_image() {
         let container_runner = &get_container_manager();
         let name = "test_case_5";
         let image_name = "non_existent_image";
         let cmd = "ls";
 
-        assert!(run_container(container_runner, name, image_name, cmd, true).is_err());
+        assert!(run_container(
+            container_runner,
+            name,
+            image_name,
+            &ContainerCommand::shell(cmd),
+            true
+        )
+        .is_err());
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_remove_container() {
         let container_runner = &get_container_manager();
         let name = "test_case_remove_1";
@@ -349,13 +455,20 @@ mod tests {
         let cmd = "ls /";
 
         // First, run a container with the specified name
-        let _ = run_container(container_runner, name, image_name, cmd, true);
+        let _ = run_container(
+            container_runner,
+            name,
+            image_name,
+            &ContainerCommand::shell(cmd),
+            true,
+        );
 
         // Then, try to remove the container
         assert!(remove_container(container_runner, name).is_ok());
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_remove_non_existent_container() {
         let container_runner = &get_container_manager();
         let name = "test_case_remove_2";
@@ -365,6 +478,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_commit_container_with_file() {
         let container_runner = &get_container_manager();
         let container_name = "test_commit_with_file";
@@ -374,11 +488,18 @@ mod tests {
         // Create a file in the container using the echo command
         let cmd = "bash -c 'echo \"Hello, World!\" > /testfile.txt'";
         let _ = remove_container(container_runner, container_name);
-        run_container(container_runner, container_name, image_name, cmd, true).unwrap();
+        run_container(
+            container_runner,
+            container_name,
+            image_name,
+            &ContainerCommand::shell(cmd),
+            true,
+        )
+        .unwrap();
 
         // Commit the container to a new image
         let new_image_name = "test_commit_image_with_file";
-        let result = commit_container(container_runner, container_name, new_image_name, &vec![]);
+        let result = commit_container(container_runner, container_name, new_image_name, &[]);
         assert!(result.is_ok(), "Commit failed: {:?}", result.err());
 
         // Run a new container with the new image and check if the file exists
@@ -388,7 +509,7 @@ mod tests {
             container_runner,
             container2_name,
             new_image_name,
-            cmd_check_file,
+            &ContainerCommand::shell(cmd_check_file),
             true,
         );
         assert!(run_result.is_ok(), "File not found: {:?}", run_result.err());
@@ -408,12 +529,13 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_commit_invalid_container() {
         let container_runner = &get_container_manager();
         let name = "invalid_container";
         let image_name = "new_image_from_invalid_container";
 
-        let result = commit_container(container_runner, name, image_name, &vec![]);
+        let result = commit_container(container_runner, name, image_name, &[]);
 
         assert!(
             result.is_err(),
@@ -422,16 +544,25 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_remove_image() {
         let container_runner = &get_container_manager();
         let name = "test_case_remove_image_1";
         let image_name = "ubuntu";
-        run_container(container_runner, "", image_name, "ls", false).unwrap(); // for pull image
+        run_container(
+            container_runner,
+            "",
+            image_name,
+            &ContainerCommand::exec(&["ls"]),
+            false,
+        )
+        .unwrap(); // for pull image
         tag_image(container_runner, image_name, name).unwrap();
         assert!(remove_image(container_runner, name).is_ok());
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_remove_non_existent_image() {
         let container_runner = &get_container_manager();
         let name = "test_case_remove_image_non_existent";
@@ -441,12 +572,20 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_find_images_single_filter() {
         let container_runner = &get_container_manager();
         let image_name = "ubuntu";
         let container_name = "test_find_images_single_container";
         let _ = remove_container(container_runner, container_name);
-        run_container(container_runner, container_name, image_name, "ls", false).unwrap();
+        run_container(
+            container_runner,
+            container_name,
+            image_name,
+            &ContainerCommand::exec(&["ls"]),
+            false,
+        )
+        .unwrap();
         let new_image_name = "test_find_images_single_image";
         let new_image_other_name = "test_find_images_single_image_other";
         let filters = vec!["label=test=find_images_single_filter"];
@@ -458,7 +597,7 @@ mod tests {
 
             for image in find_images(
                 container_runner,
-                &vec!["label=test=find_images_single_filter_other"],
+                &["label=test=find_images_single_filter_other"],
             )
             .unwrap()
             {
@@ -471,14 +610,14 @@ mod tests {
             container_runner,
             container_name,
             new_image_name,
-            &vec!["LABEL test=find_images_single_filter"],
+            &["LABEL test=find_images_single_filter"],
         )
         .unwrap();
         commit_container(
             container_runner,
             container_name,
             new_image_other_name,
-            &vec!["LABEL test=find_images_single_filter_other"],
+            &["LABEL test=find_images_single_filter_other"],
         )
         .unwrap();
 
@@ -490,12 +629,20 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_find_images_multiple_filters() {
         let container_runner = &get_container_manager();
         let image_name = "ubuntu";
         let container_name = "test_find_images_multiple_container";
         let _ = remove_container(container_runner, container_name);
-        run_container(container_runner, container_name, image_name, "ls", false).unwrap();
+        run_container(
+            container_runner,
+            container_name,
+            image_name,
+            &ContainerCommand::exec(&["ls"]),
+            false,
+        )
+        .unwrap();
 
         let new_image_name = "test_find_images_multiple_image";
         let new_image_other_name = "test_find_images_multiple_other_image";
@@ -507,7 +654,7 @@ mod tests {
             }
             for image in find_images(
                 container_runner,
-                &vec!["label=test=find_images_multiple_filters", "label=test2=1"],
+                &["label=test=find_images_multiple_filters", "label=test2=1"],
             )
             .unwrap()
             {
@@ -520,14 +667,14 @@ mod tests {
             container_runner,
             container_name,
             new_image_name,
-            &vec!["LABEL test=find_images_multiple_filters", "LABEL test2=0"],
+            &["LABEL test=find_images_multiple_filters", "LABEL test2=0"],
         )
         .unwrap();
         commit_container(
             container_runner,
             container_name,
             new_image_other_name,
-            &vec!["LABEL test=find_images_multiple_filters", "LABEL test2=1"],
+            &["LABEL test=find_images_multiple_filters", "LABEL test2=1"],
         )
         .unwrap();
 
@@ -540,6 +687,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_find_images_no_match() {
         let container_runner = &get_container_manager();
         let filters = vec!["label=nonexistent"];
@@ -550,6 +698,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_find_images_invalid_filter() {
         let container_runner = &get_container_manager();
         let filters = vec!["invalidfilter"];
@@ -560,6 +709,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_check_container_exists() {
         let container_runner = &get_container_manager();
         let name = "test_check_container_exists";
@@ -568,111 +718,68 @@ mod tests {
         // Before run the container
         let result = check_container_exists(container_runner, name);
         assert!(result.is_ok());
-        assert_eq!(false, result.unwrap());
+        assert!(!result.unwrap());
 
         // After run the container
         let image_name = "ubuntu";
         let cmd = "ls";
-        let _ = run_container(container_runner, name, image_name, cmd, true);
+        let _ = run_container(
+            container_runner,
+            name,
+            image_name,
+            &ContainerCommand::shell(cmd),
+            true,
+        );
         let result = check_container_exists(container_runner, name);
         let _ = remove_container(container_runner, name);
 
         assert!(result.is_ok());
-        assert_eq!(true, result.unwrap());
-    }
-
-    #[test]
-    fn test_pin_image() {
-        let container_runner = &get_container_manager();
-        let _ = unpin_image(container_runner, "ubuntu:latest");
-        // Attempt to pin the ubuntu image
-        let result = pin_image(container_runner, "ubuntu:latest");
-        assert!(result.is_ok(), "Failed to pin the ubuntu image");
-
-        // Attempt to pin the same image again, expect an error
-        let result = pin_image(container_runner, "ubuntu:latest");
-        assert!(
-            result.is_err(),
-            "Should fail when attempting to pin the same image twice"
-        );
-        let _ = unpin_image(container_runner, "ubuntu:latest");
-    }
-    #[test]
-    fn test_pin_image_nonexistent() {
-        let container_runner = &get_container_manager();
-        // Attempt to pin an image that does not exist, expect an error
-        let result = pin_image(container_runner, "localhost/nonexistent1");
-        assert!(
-            result.is_err(),
-            "Should fail when attempting to pin a nonexistent image"
-        );
-    }
-
-    #[test]
-    fn test_unpin_image() {
-        let container_runner = &get_container_manager();
-        let _ = unpin_image(container_runner, "ubuntu");
-        // Pin the ubuntu image
-        let _ = pin_image(container_runner, "ubuntu");
-        // Unpin the pinned image
-        let unpin_result = unpin_image(container_runner, "ubuntu");
-        assert!(unpin_result.is_ok(), "Failed to unpin the ubuntu image");
-    }
-    #[test]
-    fn test_unpin_image_nonexistent() {
-        let container_runner = &get_container_manager();
-        // Attempt to unpin an image that does not exist, expect an error
-        let result = unpin_image(container_runner, "localhost/nonexistent1");
-        assert!(
-            result.is_err(),
-            "Should fail when attempting to unpin a nonexistent image"
-        );
-    }
-    #[test]
-    fn test_unpin_image_stopped() {
-        let container_runner = &get_container_manager();
-        let image_name = "alpine"; // use different image than test_unpin_image
-        let _ = unpin_image(container_runner, image_name);
-        // Pin the ubuntu image
-        let _ = pin_image(container_runner, image_name);
-
-        // Stop the pinned container
-        let _ = stop_container_with_args(
-            container_runner,
-            &format!("pin-{}", image_name),
-            &["-t", "0"],
-        );
-
-        // Attempt to unpin the stopped container, expect success
-        let result = unpin_image(container_runner, image_name);
-        assert!(
-            result.is_ok(),
-            "Failed to unpin the stopped ubuntu container"
-        );
+        assert!(result.unwrap());
     }
 
     #[test]
-    fn test_build_image_from_dockerfile_simple() {
+    #[ignore = "needs a container engine and pulls images"]
+    fn test_build_image_from_dockerfile_with_args() {
         let container_runner = &get_container_manager();
-        let name = "test_build_image_from_dockerfile_simple";
+        let name = "test_build_image_from_dockerfile_with_args";
         let mut path = env::current_dir().unwrap();
         path.push("tests/files/example_dockerfile_build_image");
         let dockerfile_path = path.to_str().unwrap().to_owned();
         let context = ".";
         let _ = remove_image(container_runner, name);
-        let result =
-            build_image_from_dockerfile_simple(container_runner, name, &dockerfile_path, context);
+        let result = build_image_from_dockerfile_with_args(
+            container_runner,
+            name,
+            &dockerfile_path,
+            context,
+            &["--build-arg", "UNUSED=1"],
+        );
         assert!(result.is_ok());
         let cmd = "fish -c 'ls /build_image_dockerfile_test'";
-        assert!(run_container(container_runner, "", name, cmd, true).is_ok());
+        assert!(run_container(
+            container_runner,
+            "",
+            name,
+            &ContainerCommand::shell(cmd),
+            true
+        )
+        .is_ok());
         let _ = remove_image(container_runner, name);
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_inspect_image() {
         let container_runner = &get_container_manager();
         let image_name = "ubuntu";
-        run_container(container_runner, "", image_name, "ls", false).unwrap(); // for pull image
+        run_container(
+            container_runner,
+            "",
+            image_name,
+            &ContainerCommand::exec(&["ls"]),
+            false,
+        )
+        .unwrap(); // for pull image
 
         let result = inspect_image(container_runner, image_name, ".Id");
         assert!(result.is_ok());
@@ -680,6 +787,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_inspect_image_nonexistent_image() {
         let container_runner = &get_container_manager();
         let image_name = "nonexistent_image";
@@ -689,20 +797,36 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_inspect_image_invalid_format() {
         let container_runner = &get_container_manager();
         let image_name = "ubuntu";
-        run_container(container_runner, "", image_name, "ls", false).unwrap(); // for pull image
+        run_container(
+            container_runner,
+            "",
+            image_name,
+            &ContainerCommand::exec(&["ls"]),
+            false,
+        )
+        .unwrap(); // for pull image
 
         let result = inspect_image(container_runner, image_name, "112233");
         assert!(result.is_err());
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_inspect_image_nonexistent_config() {
         let container_runner = &get_container_manager();
         let image_name = "ubuntu";
-        run_container(container_runner, "", image_name, "ls", false).unwrap(); // for pull image
+        run_container(
+            container_runner,
+            "",
+            image_name,
+            &ContainerCommand::exec(&["ls"]),
+            false,
+        )
+        .unwrap(); // for pull image
 
         let result = inspect_image(container_runner, image_name, ".Config.Labels.Non");
         assert!(result.is_ok());
@@ -713,6 +837,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_list_images_by_prefix() {
         let container_runner = &get_container_manager();
         // Test with a non-existent prefix - should return empty list
@@ -722,6 +847,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_list_images_by_prefix_with_images() {
         let container_runner = &get_container_manager();
         let test_id = format!("{}", std::process::id());
@@ -735,14 +861,21 @@ mod tests {
         let _ = remove_image(container_runner, &test_image);
 
         // Create a test image with the prefix
-        run_container(container_runner, &container_name, image_name, "ls", false).unwrap();
-        commit_container(container_runner, &container_name, &test_image, &vec![]).unwrap();
+        run_container(
+            container_runner,
+            &container_name,
+            image_name,
+            &ContainerCommand::exec(&["ls"]),
+            false,
+        )
+        .unwrap();
+        commit_container(container_runner, &container_name, &test_image, &[]).unwrap();
 
         // List images with the prefix
         let result = list_images_by_prefix(container_runner, &test_prefix);
         assert!(result.is_ok());
         let images = result.unwrap();
-        assert!(images.len() >= 1, "Should find at least one image");
+        assert!(!images.is_empty(), "Should find at least one image");
         assert!(
             images.iter().any(|img| img.contains(&test_prefix)),
             "Should contain image with test prefix"
@@ -754,6 +887,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_export_images_empty() {
         let container_runner = &get_container_manager();
         let result = export_images(container_runner, &[], "/tmp/test_empty.tar");
@@ -761,6 +895,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_export_images() {
         let container_runner = &get_container_manager();
         let test_id = format!("{}", std::process::id());
@@ -776,8 +911,15 @@ mod tests {
         let _ = std::fs::remove_file(&export_path);
 
         // Create a test image
-        run_container(container_runner, &container_name, image_name, "ls", false).unwrap();
-        commit_container(container_runner, &container_name, &test_image, &vec![]).unwrap();
+        run_container(
+            container_runner,
+            &container_name,
+            image_name,
+            &ContainerCommand::exec(&["ls"]),
+            false,
+        )
+        .unwrap();
+        commit_container(container_runner, &container_name, &test_image, &[]).unwrap();
 
         // Export the image
         let images = vec![test_image.clone()];
@@ -797,6 +939,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_import_images_nonexistent_file() {
         let container_runner = &get_container_manager();
         let result = import_images(container_runner, "/nonexistent/path/file.tar");
@@ -804,6 +947,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore = "needs a container engine and pulls images"]
     fn test_import_images() {
         let container_runner = &get_container_manager();
         let test_id = format!("{}", std::process::id());
@@ -819,8 +963,15 @@ mod tests {
         let _ = std::fs::remove_file(&export_path);
 
         // Create and export a test image
-        run_container(container_runner, &container_name, image_name, "ls", false).unwrap();
-        commit_container(container_runner, &container_name, &test_image, &vec![]).unwrap();
+        run_container(
+            container_runner,
+            &container_name,
+            image_name,
+            &ContainerCommand::exec(&["ls"]),
+            false,
+        )
+        .unwrap();
+        commit_container(container_runner, &container_name, &test_image, &[]).unwrap();
         let images = vec![test_image.clone()];
         export_images(container_runner, &images, &export_path).unwrap();
 
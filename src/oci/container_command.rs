@@ -0,0 +1,193 @@
+// what runs in a container: an argv by default, `sh -c` only when asked for
+
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ContainerCommand {
+    /// Passed to the container as is, an empty argv runs the image's own command
+    Exec(Vec<String>),
+    /// Script for `sh -c`, only for trusted snippets
+    Shell(String),
+}
+
+impl Default for ContainerCommand {
+    fn default() -> Self {
+        ContainerCommand::Exec(vec![])
+    }
+}
+
+impl ContainerCommand {
+    pub fn exec<S: AsRef<str>>(argv: &[S]) -> Self {
+        ContainerCommand::Exec(argv.iter().map(|arg| arg.as_ref().to_string()).collect())
+    }
+
+    pub fn shell(script: &str) -> Self {
+        ContainerCommand::Shell(script.to_string())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            ContainerCommand::Exec(argv) => argv.is_empty(),
+            ContainerCommand::Shell(script) => script.is_empty(),
+        }
+    }
+
+    /// Equivalent `sh` script, with every argument of an argv quoted
+    pub fn to_script(&self) -> String {
+        match self {
+            ContainerCommand::Exec(argv) => argv
+                .iter()
+                .map(|arg| shell_quote(arg))
+                .collect::<Vec<String>>()
+                .join(" "),
+            ContainerCommand::Shell(script) => script.clone(),
+        }
+    }
+
+    /// `run` arguments after the options: entrypoint, image and command
+    pub fn run_args<'a>(&'a self, image_name: &'a str) -> Vec<&'a str> {
+        match self {
+            ContainerCommand::Exec(argv) => match argv.split_first() {
+                Some((entrypoint, args)) => {
+                    let mut run_args = vec!["--entrypoint", entrypoint.as_str(), image_name];
+                    run_args.extend(args.iter().map(String::as_str));
+                    run_args
+                }
+                None => vec![image_name],
+            },
+            ContainerCommand::Shell(script) => {
+                vec!["--entrypoint", "sh", image_name, "-c", script.as_str()]
+            }
+        }
+    }
+}
+
+impl fmt::Display for ContainerCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_script())
+    }
+}
+
+/// An argv array, or `{ shell = "..." }` to opt into `sh -c`
+impl<'de> Deserialize<'de> for ContainerCommand {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct CommandVisitor;
+
+        impl<'de> Visitor<'de> for CommandVisitor {
+            type Value = ContainerCommand;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an argv array or {{ shell = \"...\" }}")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut argv = vec![];
+                while let Some(arg) = seq.next_element::<String>()? {
+                    argv.push(arg);
+                }
+                Ok(ContainerCommand::Exec(argv))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut script = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    if key != "shell" {
+                        return Err(de::Error::unknown_field(&key, &["shell"]));
+                    }
+                    script = Some(map.next_value::<String>()?);
+                }
+                script
+                    .map(ContainerCommand::Shell)
+                    .ok_or_else(|| de::Error::missing_field("shell"))
+            }
+        }
+
+        deserializer.deserialize_any(CommandVisitor)
+    }
+}
+
+/// Quote `arg` for `sh` unless it only has characters the shell leaves alone
+pub fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_+./:@%,^".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Run `commands` one after another in one `sh -c`, stopping at the first failure
+pub fn chain_commands(commands: &[ContainerCommand]) -> ContainerCommand {
+    match commands {
+        [command] => command.clone(),
+        _ => ContainerCommand::Shell(
+            commands
+                .iter()
+                .filter(|command| !command.is_empty())
+                .map(|command| command.to_script())
+                .collect::<Vec<String>>()
+                .join(" && "),
+        ),
+    }
+}
+
+/// Shell snippet that runs `command` and ignores its failure
+pub fn ignore_failure(command: &ContainerCommand) -> ContainerCommand {
+    ContainerCommand::Shell(format!("{{ {} || true; }}", command.to_script()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("apt-get"), "apt-get");
+        assert_eq!(shell_quote("libc6:amd64"), "libc6:amd64");
+        assert_eq!(shell_quote("foo; rm -rf /"), "'foo; rm -rf /'");
+        assert_eq!(shell_quote("$(id)"), "'$(id)'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_container_command_run_args() {
+        let command = ContainerCommand::exec(&["apt-get", "install", "-y", "foo; rm -rf /"]);
+        assert_eq!(
+            command.run_args("ubuntu"),
+            vec![
+                "--entrypoint",
+                "apt-get",
+                "ubuntu",
+                "install",
+                "-y",
+                "foo; rm -rf /"
+            ]
+        );
+        assert_eq!(command.to_string(), "apt-get install -y 'foo; rm -rf /'");
+        assert_eq!(
+            ContainerCommand::shell("echo $HOME").run_args("ubuntu"),
+            vec!["--entrypoint", "sh", "ubuntu", "-c", "echo $HOME"]
+        );
+        assert_eq!(
+            ContainerCommand::default().run_args("ubuntu"),
+            vec!["ubuntu"]
+        );
+    }
+
+    #[test]
+    fn test_chain_commands() {
+        let update = ContainerCommand::exec(&["apt-get", "update"]);
+        let install = ContainerCommand::exec(&["apt-get", "install", "-y", "a b"]);
+        assert_eq!(chain_commands(std::slice::from_ref(&update)), update);
+        assert_eq!(
+            chain_commands(&[update, ignore_failure(&install)]),
+            ContainerCommand::shell("apt-get update && { apt-get install -y 'a b' || true; }")
+        );
+    }
+}
@@ -0,0 +1,102 @@
+// abstraction over the OCI engine (podman/docker) used to build and run images
+
+use crate::oci::cli_runtime::{CliFlavor, CliRuntime};
+use crate::oci::container_command::ContainerCommand;
+use crate::utils::command_helper::{CommandError, CommandOutput};
+
+/// State of the engine as `doctor` reports it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RuntimeInfo {
+    pub version: String,
+    pub rootless: bool,
+    pub storage_driver: String,
+}
+
+pub trait ContainerRuntime: Sync {
+    /// Executable of the engine, e.g. "podman" or "/usr/bin/docker"
+    fn name(&self) -> &str;
+
+    fn run_container_with_args(
+        &self,
+        name: &str,
+        image_name: &str,
+        cmd: &ContainerCommand,
+        extra_args: &[&str],
+        realtime_output: bool,
+    ) -> Result<CommandOutput, CommandError>;
+
+    /// Run `cmd` in a new container, an empty `name` removes it afterwards
+    fn run_container(
+        &self,
+        name: &str,
+        image_name: &str,
+        cmd: &ContainerCommand,
+        realtime_output: bool,
+    ) -> Result<CommandOutput, CommandError>;
+
+    fn stop_container_with_args(
+        &self,
+        name: &str,
+        extra_args: &[&str],
+    ) -> Result<CommandOutput, CommandError>;
+
+    fn remove_container(&self, name: &str) -> Result<CommandOutput, CommandError>;
+
+    fn check_container_exists(&self, container_name: &str) -> Result<bool, CommandError>;
+
+    fn commit_container(
+        &self,
+        container_name: &str,
+        image_name: &str,
+        instructions: &[&str],
+    ) -> Result<CommandOutput, CommandError>;
+
+    fn remove_image(&self, name: &str) -> Result<CommandOutput, CommandError>;
+
+    fn find_images(&self, filters: &[&str]) -> Result<Vec<String>, CommandError>;
+
+    fn tag_image(&self, name: &str, new_name: &str) -> Result<CommandOutput, CommandError>;
+
+    fn inspect_image(&self, image_name: &str, format: &str) -> Result<String, CommandError>;
+
+    /// Build with extra `build` options such as `--build-arg` or `--target`
+    fn build_image_from_dockerfile_with_args(
+        &self,
+        image_name: &str,
+        dockerfile_path: &str,
+        context: &str,
+        extra_args: &[&str],
+    ) -> Result<CommandOutput, CommandError>;
+
+    fn list_images_by_prefix(&self, prefix: &str) -> Result<Vec<String>, CommandError>;
+
+    fn export_images(&self, images: &[String], output_path: &str) -> Result<(), CommandError>;
+
+    fn import_images(&self, input_path: &str) -> Result<String, CommandError>;
+
+    fn info(&self) -> Result<RuntimeInfo, CommandError>;
+}
+
+pub fn new_container_runtime(container_manager: &str) -> Box<dyn ContainerRuntime> {
+    Box::new(CliRuntime::new(
+        container_manager,
+        CliFlavor::detect(container_manager),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_container_runtime() {
+        assert_eq!(new_container_runtime("podman").name(), "podman");
+        assert_eq!(new_container_runtime("docker").name(), "docker");
+        assert_eq!(
+            new_container_runtime("/usr/bin/docker").name(),
+            "/usr/bin/docker"
+        );
+        assert_eq!(CliFlavor::detect("/usr/bin/docker"), CliFlavor::Docker);
+        assert_eq!(CliFlavor::detect("podman-remote"), CliFlavor::Podman);
+    }
+}
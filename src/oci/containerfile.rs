@@ -0,0 +1,120 @@
+// write the layers of build_image as a Containerfile for plain `podman build`
+
+use crate::config::get_distrobox_setup_marker;
+use crate::distro::distrobox_requirements::get_distrobox_packages;
+use crate::distro::package_manager::*;
+use crate::oci::container_command::ContainerCommand;
+use crate::oci::container_runtime::ContainerRuntime;
+use crate::oci::image_builder::get_image_os_release;
+use crate::utils::command_helper::CommandError;
+
+/// Exec form for an argv so nothing goes through a shell, shell form for a script
+fn run_instruction(cmd: &ContainerCommand) -> String {
+    match cmd {
+        ContainerCommand::Exec(argv) => {
+            format!("RUN {}", serde_json::to_string(argv).unwrap())
+        }
+        ContainerCommand::Shell(script) => format!("RUN {}", script),
+    }
+}
+
+fn quote_label_value(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Same steps as `build_image`, without the labels that refer to image ids
+pub fn generate_containerfile(
+    runtime: &dyn ContainerRuntime,
+    base_image: &str,
+    request_package_manager: &Option<String>,
+    packages: &[String],
+    distrobox_mode: bool,
+) -> Result<String, CommandError> {
+    let os_release = get_image_os_release(runtime, base_image)?;
+    let package_manager = request_package_manager
+        .clone()
+        .unwrap_or(get_package_manager(&os_release));
+
+    validate_package_names(&package_manager, packages)?;
+
+    let mut lines = vec![format!("FROM {}", base_image)];
+    let mut labels = vec![("image", base_image.to_string())];
+    let mut status = "db_update";
+    lines.push(run_instruction(&generate_update_command(&package_manager)));
+    if distrobox_mode {
+        let requirements = get_distrobox_packages(&os_release);
+        lines.push(run_instruction(&generate_install_command_with_optional(
+            &package_manager,
+            &requirements.packages,
+            &requirements.optional_packages,
+            &requirements.optional_scripts,
+        )));
+        labels.push(("packages0", requirements.packages.join(";")));
+        status = "distrobox_pre_install";
+    }
+    if !packages.is_empty() {
+        for package in packages {
+            lines.push(run_instruction(&generate_install_command(
+                &package_manager,
+                &[package.as_str()],
+            )));
+        }
+        labels.push(("package1", packages.join(";")));
+        status = "package_install";
+    }
+    if let Some(setup_marker) = get_distrobox_setup_marker().filter(|_| distrobox_mode) {
+        lines.push(run_instruction(&ContainerCommand::exec(&[
+            "touch",
+            setup_marker.as_str(),
+        ])));
+        status = "distrobox_setup";
+    }
+    labels.push(("status", status.to_string()));
+
+    labels.sort();
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, quote_label_value(value)))
+        .collect();
+    lines.push(format!("LABEL {}", labels.join(" \\\n      ")));
+    Ok(lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oci::fake_runtime::{FakeRuntime, UBUNTU_OS_RELEASE};
+
+    #[test]
+    fn test_generate_containerfile() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let packages = vec!["fish".to_string(), "htop".to_string()];
+
+        let content = generate_containerfile(&runtime, "ubuntu", &None, &packages, false).unwrap();
+        assert_eq!(
+            content,
+            format!(
+                "FROM ubuntu\nRUN [\"apt-get\",\"update\"]\nRUN [\"apt-get\",\"install\",\"-y\",\"fish\"]\nRUN [\"apt-get\",\"install\",\"-y\",\"htop\"]\nLABEL image=\"ubuntu\" \\\n      package1=\"fish;htop\" \\\n      status=\"package_install\"\n",
+            )
+        );
+
+        let content = generate_containerfile(&runtime, "ubuntu", &None, &[], true).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert!(lines[2].starts_with("RUN apt-get install"));
+        assert_eq!(lines[3], "RUN [\"touch\",\"/run/.containersetupdone\"]");
+        assert!(content.contains("status=\"distrobox_setup\""));
+    }
+
+    #[test]
+    fn test_generate_containerfile_invalid_package() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let packages = vec!["fish; rm -rf /".to_string()];
+        assert!(generate_containerfile(&runtime, "ubuntu", &None, &packages, false).is_err());
+    }
+
+    #[test]
+    fn test_quote_label_value() {
+        assert_eq!(quote_label_value("a;b"), "\"a;b\"");
+        assert_eq!(quote_label_value("say \"hi\""), "\"say \\\"hi\\\"\"");
+    }
+}
@@ -0,0 +1,546 @@
+// in-memory ContainerRuntime for tests, records every call instead of running an engine
+
+use crate::oci::container_command::ContainerCommand;
+use crate::oci::container_runtime::{ContainerRuntime, RuntimeInfo};
+use crate::utils::command_helper::{CommandError, CommandOutput};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+pub const UBUNTU_OS_RELEASE: &str = r#"PRETTY_NAME="Ubuntu 22.04.3 LTS"
+NAME="Ubuntu"
+VERSION_ID="22.04"
+VERSION="22.04.3 LTS (Jammy Jellyfish)"
+VERSION_CODENAME=jammy
+ID=ubuntu
+ID_LIKE=debian
+"#;
+
+#[derive(Clone)]
+pub struct FakeImage {
+    pub id: String,
+    pub parent: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    /// Every command run on the way from the base image to this one
+    pub commands: Vec<String>,
+    os_release: String,
+}
+
+struct FakeContainer {
+    image_id: String,
+    cmd: String,
+}
+
+#[derive(Default)]
+struct FakeState {
+    images: HashMap<String, FakeImage>,
+    tags: BTreeMap<String, String>,
+    containers: HashMap<String, FakeContainer>,
+    /// (command prefix, stdout, command that must have run in the image)
+    outputs: Vec<(String, String, Option<String>)>,
+    failures: Vec<String>,
+    calls: Vec<String>,
+    next_id: u64,
+}
+
+impl FakeState {
+    fn resolve(&self, reference: &str) -> Option<String> {
+        if let Some(id) = self.tags.get(&normalize_reference(reference)) {
+            return Some(id.clone());
+        }
+        if self.images.contains_key(reference) {
+            return Some(reference.to_string());
+        }
+        None
+    }
+
+    fn new_image_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("{:012x}", self.next_id)
+    }
+}
+
+fn normalize_reference(reference: &str) -> String {
+    let name = reference
+        .rsplit_once('/')
+        .map_or(reference, |(_, name)| name);
+    if name.contains(':') || name.contains('@') {
+        reference.to_string()
+    } else {
+        format!("{}:latest", reference)
+    }
+}
+
+fn fake_error(stderr: String) -> CommandError {
+    CommandError {
+        stdout: String::new(),
+        stderr,
+        status: Some(125),
+        inner: None,
+    }
+}
+
+fn fake_output(stdout: String) -> CommandOutput {
+    CommandOutput {
+        stdout,
+        stderr: String::new(),
+        status: Some(0),
+    }
+}
+
+#[derive(Default)]
+pub struct FakeRuntime {
+    state: Mutex<FakeState>,
+}
+
+impl FakeRuntime {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a pullable base image whose `/etc/os-release` is `os_release`
+    pub fn with_image(self, reference: &str, os_release: &str) -> Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            let id = state.new_image_id();
+            state.images.insert(
+                id.clone(),
+                FakeImage {
+                    id: id.clone(),
+                    parent: None,
+                    labels: BTreeMap::new(),
+                    commands: vec![],
+                    os_release: os_release.to_string(),
+                },
+            );
+            state.tags.insert(normalize_reference(reference), id);
+        }
+        self
+    }
+
+    /// Make every command starting with `cmd_prefix` print `stdout`
+    pub fn with_output(self, cmd_prefix: &str, stdout: &str) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .outputs
+            .push((cmd_prefix.to_string(), stdout.to_string(), None));
+        self
+    }
+
+    /// Like `with_output`, but only in images built by a command starting with `after_cmd`
+    pub fn with_layer_output(self, after_cmd: &str, cmd_prefix: &str, stdout: &str) -> Self {
+        self.state.lock().unwrap().outputs.push((
+            cmd_prefix.to_string(),
+            stdout.to_string(),
+            Some(after_cmd.to_string()),
+        ));
+        self
+    }
+
+    /// Make every command containing `pattern` exit with an error
+    pub fn with_failure(self, pattern: &str) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .push(pattern.to_string());
+        self
+    }
+
+    /// All calls made so far, e.g. "run ubuntu:latest apt-get update"
+    pub fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// Commands passed to `run`, in order
+    pub fn run_commands(&self) -> Vec<String> {
+        self.calls()
+            .iter()
+            .filter_map(|call| call.strip_prefix("run "))
+            .filter_map(|call| call.split_once(' ').map(|(_, cmd)| cmd.to_string()))
+            .collect()
+    }
+
+    pub fn image(&self, reference: &str) -> Option<FakeImage> {
+        let state = self.state.lock().unwrap();
+        let id = state.resolve(reference)?;
+        state.images.get(&id).cloned()
+    }
+
+    pub fn container_names(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let mut names: Vec<String> = state.containers.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+impl ContainerRuntime for FakeRuntime {
+    fn name(&self) -> &str {
+        "fake"
+    }
+
+    fn run_container(
+        &self,
+        name: &str,
+        image_name: &str,
+        cmd: &ContainerCommand,
+        realtime_output: bool,
+    ) -> Result<CommandOutput, CommandError> {
+        let args: &[&str] = if name.is_empty() { &["--rm"] } else { &[] };
+        self.run_container_with_args(name, image_name, cmd, args, realtime_output)
+    }
+
+    fn run_container_with_args(
+        &self,
+        name: &str,
+        image_name: &str,
+        cmd: &ContainerCommand,
+        extra_args: &[&str],
+        _realtime_output: bool,
+    ) -> Result<CommandOutput, CommandError> {
+        let cmd = cmd.to_string();
+        let cmd = cmd.as_str();
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(format!("run {} {}", image_name, cmd));
+        let image_id = state
+            .resolve(image_name)
+            .ok_or_else(|| fake_error(format!("image not known: {}", image_name)))?;
+        if !name.is_empty() && state.containers.contains_key(name) {
+            return Err(fake_error(format!("container name {} in use", name)));
+        }
+        if !name.is_empty() && !extra_args.contains(&"--rm") {
+            state.containers.insert(
+                name.to_string(),
+                FakeContainer {
+                    image_id: image_id.clone(),
+                    cmd: cmd.to_string(),
+                },
+            );
+        }
+        if state.failures.iter().any(|pattern| cmd.contains(pattern)) {
+            return Err(fake_error(format!("command failed: {}", cmd)));
+        }
+        let stdout = if cmd == "cat /etc/os-release" {
+            state.images[&image_id].os_release.clone()
+        } else {
+            let commands = &state.images[&image_id].commands;
+            state
+                .outputs
+                .iter()
+                .rev()
+                .find(|(prefix, _, after_cmd)| {
+                    cmd.starts_with(prefix.as_str())
+                        && after_cmd
+                            .as_ref()
+                            .is_none_or(|after| commands.iter().any(|c| c.starts_with(after)))
+                })
+                .map(|(_, stdout, _)| stdout.clone())
+                .unwrap_or_default()
+        };
+        Ok(fake_output(stdout))
+    }
+
+    fn stop_container_with_args(
+        &self,
+        name: &str,
+        _extra_args: &[&str],
+    ) -> Result<CommandOutput, CommandError> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(format!("stop {}", name));
+        if !state.containers.contains_key(name) {
+            return Err(fake_error(format!("no such container: {}", name)));
+        }
+        Ok(fake_output(String::new()))
+    }
+
+    fn remove_container(&self, name: &str) -> Result<CommandOutput, CommandError> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(format!("rm {}", name));
+        state
+            .containers
+            .remove(name)
+            .map(|_| fake_output(String::new()))
+            .ok_or_else(|| fake_error(format!("no such container: {}", name)))
+    }
+
+    fn check_container_exists(&self, container_name: &str) -> Result<bool, CommandError> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .containers
+            .contains_key(container_name))
+    }
+
+    fn commit_container(
+        &self,
+        container_name: &str,
+        image_name: &str,
+        instructions: &[&str],
+    ) -> Result<CommandOutput, CommandError> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .calls
+            .push(format!("commit {} {}", container_name, image_name));
+        let container = state
+            .containers
+            .get(container_name)
+            .ok_or_else(|| fake_error(format!("no such container: {}", container_name)))?;
+        let parent = state.images[&container.image_id].clone();
+        let cmd = container.cmd.clone();
+        let mut labels = parent.labels.clone();
+        for instruction in instructions {
+            if let Some(label) = instruction.strip_prefix("LABEL ") {
+                let (key, value) = label.split_once('=').unwrap_or((label, ""));
+                labels.insert(key.to_string(), value.to_string());
+            }
+        }
+        let mut commands = parent.commands.clone();
+        if !cmd.is_empty() {
+            commands.push(cmd);
+        }
+        let id = state.new_image_id();
+        state.images.insert(
+            id.clone(),
+            FakeImage {
+                id: id.clone(),
+                parent: Some(parent.id),
+                labels,
+                commands,
+                os_release: parent.os_release,
+            },
+        );
+        state
+            .tags
+            .insert(normalize_reference(image_name), id.clone());
+        Ok(fake_output(id))
+    }
+
+    fn remove_image(&self, name: &str) -> Result<CommandOutput, CommandError> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(format!("rmi {}", name));
+        if state.tags.remove(&normalize_reference(name)).is_some() {
+            return Ok(fake_output(String::new()));
+        }
+        if state.images.remove(name).is_some() {
+            state.tags.retain(|_, id| id != name);
+            return Ok(fake_output(String::new()));
+        }
+        Err(fake_error(format!("image not known: {}", name)))
+    }
+
+    fn find_images(&self, filters: &[&str]) -> Result<Vec<String>, CommandError> {
+        let state = self.state.lock().unwrap();
+        let mut label_filters = vec![];
+        for filter in filters {
+            let label = filter
+                .strip_prefix("label=")
+                .ok_or_else(|| fake_error(format!("invalid filter: {}", filter)))?;
+            label_filters.push(label.split_once('=').unwrap_or((label, "")));
+        }
+        let mut ids: Vec<String> = state
+            .images
+            .values()
+            .filter(|image| {
+                label_filters.iter().all(|(key, value)| {
+                    image
+                        .labels
+                        .get(*key)
+                        .is_some_and(|v| value.is_empty() || v == value)
+                })
+            })
+            .map(|image| image.id.clone())
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn tag_image(&self, name: &str, new_name: &str) -> Result<CommandOutput, CommandError> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(format!("tag {} {}", name, new_name));
+        let id = state
+            .resolve(name)
+            .ok_or_else(|| fake_error(format!("image not known: {}", name)))?;
+        state.tags.insert(normalize_reference(new_name), id);
+        Ok(fake_output(String::new()))
+    }
+
+    fn inspect_image(&self, image_name: &str, format: &str) -> Result<String, CommandError> {
+        let state = self.state.lock().unwrap();
+        let id = state
+            .resolve(image_name)
+            .ok_or_else(|| fake_error(format!("image not known: {}", image_name)))?;
+        let image = &state.images[&id];
+        let field = format.trim_matches(|c| c == '{' || c == '}' || c == ' ');
+        if field == ".Id" {
+            return Ok(image.id.clone());
+        }
+        let label = field
+            .strip_prefix(".Config.Labels.")
+            .or_else(|| field.strip_prefix(".Labels."))
+            .ok_or_else(|| fake_error(format!("unsupported format: {}", format)))?;
+        Ok(image.labels.get(label).cloned().unwrap_or_default())
+    }
+
+    fn build_image_from_dockerfile_with_args(
+        &self,
+        image_name: &str,
+        dockerfile_path: &str,
+        context: &str,
+        extra_args: &[&str],
+    ) -> Result<CommandOutput, CommandError> {
+        let dockerfile = std::fs::read_to_string(dockerfile_path)
+            .map_err(|e| fake_error(format!("{}: {}", dockerfile_path, e)))?;
+        let mut state = self.state.lock().unwrap();
+        let mut call = format!("build {} {} {}", image_name, dockerfile_path, context);
+        for arg in extra_args {
+            call.push(' ');
+            call.push_str(arg);
+        }
+        state.calls.push(call);
+        let from = dockerfile
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("FROM "))
+            .ok_or_else(|| fake_error(format!("no FROM in {}", dockerfile_path)))?;
+        let parent_id = state
+            .resolve(from.trim())
+            .ok_or_else(|| fake_error(format!("image not known: {}", from)))?;
+        let parent = state.images[&parent_id].clone();
+        let mut labels = parent.labels;
+        for pair in extra_args.windows(2) {
+            if pair[0] == "--label" {
+                let (key, value) = pair[1].split_once('=').unwrap_or((pair[1], ""));
+                labels.insert(key.to_string(), value.to_string());
+            }
+        }
+        let id = state.new_image_id();
+        state.images.insert(
+            id.clone(),
+            FakeImage {
+                id: id.clone(),
+                parent: Some(parent.id),
+                labels,
+                commands: parent.commands,
+                os_release: parent.os_release,
+            },
+        );
+        state.tags.insert(normalize_reference(image_name), id);
+        Ok(fake_output(String::new()))
+    }
+
+    fn list_images_by_prefix(&self, prefix: &str) -> Result<Vec<String>, CommandError> {
+        let state = self.state.lock().unwrap();
+        let prefix = format!("{}/", prefix);
+        Ok(state
+            .tags
+            .keys()
+            .filter(|tag| tag.starts_with(&prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn export_images(&self, images: &[String], output_path: &str) -> Result<(), CommandError> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .calls
+            .push(format!("save {} {}", output_path, images.join(" ")));
+        if images.is_empty() {
+            return Err(fake_error("No images to export".to_string()));
+        }
+        Ok(())
+    }
+
+    fn import_images(&self, input_path: &str) -> Result<String, CommandError> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(format!("load {}", input_path));
+        Ok(String::new())
+    }
+
+    fn info(&self) -> Result<RuntimeInfo, CommandError> {
+        Ok(RuntimeInfo {
+            version: "0.0.0".to_string(),
+            rootless: true,
+            storage_driver: "fake".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_run_and_commit() {
+        let runtime = FakeRuntime::new()
+            .with_image("ubuntu", UBUNTU_OS_RELEASE)
+            .with_output("echo", "hello\n");
+        let output = runtime
+            .run_container(
+                "",
+                "ubuntu",
+                &ContainerCommand::exec(&["cat", "/etc/os-release"]),
+                false,
+            )
+            .unwrap();
+        assert_eq!(output.stdout, UBUNTU_OS_RELEASE);
+        let output = runtime
+            .run_container(
+                "",
+                "ubuntu",
+                &ContainerCommand::exec(&["echo", "hello"]),
+                false,
+            )
+            .unwrap();
+        assert_eq!(output.stdout, "hello\n");
+        assert!(runtime.container_names().is_empty());
+
+        runtime
+            .run_container(
+                "c1",
+                "ubuntu:latest",
+                &ContainerCommand::exec(&["apt-get", "update"]),
+                false,
+            )
+            .unwrap();
+        runtime
+            .commit_container("c1", "updated", &["LABEL status=db_update"])
+            .unwrap();
+        runtime.remove_container("c1").unwrap();
+
+        let image = runtime.image("updated").unwrap();
+        assert_eq!(image.commands, vec!["apt-get update"]);
+        assert_eq!(image.parent, runtime.image("ubuntu").map(|i| i.id));
+        assert_eq!(
+            runtime.find_images(&["label=status=db_update"]).unwrap(),
+            vec![image.id.clone()]
+        );
+        assert_eq!(
+            runtime
+                .inspect_image("updated", ".Config.Labels.status")
+                .unwrap(),
+            "db_update"
+        );
+    }
+
+    #[test]
+    fn test_fake_errors() {
+        let runtime = FakeRuntime::new()
+            .with_image("ubuntu", UBUNTU_OS_RELEASE)
+            .with_failure("non_existent_command");
+        assert!(runtime
+            .run_container("", "missing", &ContainerCommand::exec(&["ls"]), false)
+            .is_err());
+        assert!(runtime
+            .run_container(
+                "",
+                "ubuntu",
+                &ContainerCommand::exec(&["non_existent_command"]),
+                false
+            )
+            .is_err());
+        assert!(runtime.find_images(&["invalidfilter"]).is_err());
+        assert!(runtime.remove_container("missing").is_err());
+        assert!(runtime.commit_container("missing", "x", &[]).is_err());
+    }
+}
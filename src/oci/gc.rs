@@ -0,0 +1,169 @@
+// remove builder layers that no release or pinned image is built on
+
+use crate::oci::container_runtime::ContainerRuntime;
+use crate::oci::image_builder::{get_seconds, PARENT_LAYER_LABEL};
+use crate::utils::command_helper::CommandError;
+use std::collections::HashSet;
+
+fn get_image_id(runtime: &dyn ContainerRuntime, image_name: &str) -> Result<String, CommandError> {
+    Ok(runtime.inspect_image(image_name, ".Id")?.trim().to_string())
+}
+
+fn get_label(
+    runtime: &dyn ContainerRuntime,
+    image_name: &str,
+    label: &str,
+) -> Result<String, CommandError> {
+    let value = runtime.inspect_image(image_name, &format!(".Config.Labels.{}", label))?;
+    Ok(value.trim().to_string())
+}
+
+/// Ids of `image_name` and of every layer below it, following the parent layer labels
+fn get_layer_chain(
+    runtime: &dyn ContainerRuntime,
+    image_name: &str,
+    reachable: &mut HashSet<String>,
+) -> Result<(), CommandError> {
+    let mut id = get_image_id(runtime, image_name)?;
+    while reachable.insert(id.clone()) {
+        let parent = match get_label(runtime, &id, PARENT_LAYER_LABEL) {
+            Ok(parent) => parent,
+            // the parent was removed outside of distrobox-boost
+            Err(_) => break,
+        };
+        if parent.is_empty() {
+            break;
+        }
+        id = parent;
+    }
+    Ok(())
+}
+
+/// Remove the builder tags under `image_prefix` whose layer is not below a release or
+/// pinned image, layers updated in the last `keep_days` days are kept.
+/// Returns the removed tags, or the tags that would be removed with `dry_run`.
+pub fn collect_garbage(
+    runtime: &dyn ContainerRuntime,
+    image_prefix: &str,
+    pinned_images: &[String],
+    keep_days: Option<u64>,
+    dry_run: bool,
+) -> Result<Vec<String>, CommandError> {
+    let release_prefix = format!("{}/release/", image_prefix);
+    let builder_prefix = format!("{}/builder/", image_prefix);
+    let mut builder_images = vec![];
+    let mut reachable = HashSet::new();
+    for image in runtime.list_images_by_prefix(image_prefix)? {
+        // podman lists local images as localhost/<name>
+        let name = image.strip_prefix("localhost/").unwrap_or(&image);
+        if name.starts_with(&builder_prefix) {
+            builder_images.push(image.clone());
+        }
+        if name.starts_with(&release_prefix) {
+            get_layer_chain(runtime, &image, &mut reachable)?;
+        }
+    }
+    for image in pinned_images {
+        // a pinned image that is gone protects nothing
+        if runtime.inspect_image(image, ".Id").is_ok() {
+            get_layer_chain(runtime, image, &mut reachable)?;
+        }
+    }
+
+    let min_updated_at = keep_days.map(|days| get_seconds().saturating_sub(days * 24 * 60 * 60));
+    let mut removed = vec![];
+    for image in builder_images {
+        if reachable.contains(&get_image_id(runtime, &image)?) {
+            continue;
+        }
+        if let Some(min_updated_at) = min_updated_at {
+            let updated_at = get_label(runtime, &image, "updated_at")?;
+            if updated_at.parse::<u64>().unwrap_or(0) >= min_updated_at {
+                continue;
+            }
+        }
+        if !dry_run {
+            runtime.remove_image(&image)?;
+        }
+        removed.push(image);
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::get_distrobox_boost_test_image_prefix;
+    use crate::oci::fake_runtime::{FakeRuntime, UBUNTU_OS_RELEASE};
+    use crate::oci::image_builder::build_image;
+
+    fn build(runtime: &FakeRuntime, target_image: &str, packages: &[&str]) {
+        let packages: Vec<String> = packages.iter().map(|p| p.to_string()).collect();
+        let prefix = get_distrobox_boost_test_image_prefix();
+        build_image(
+            runtime,
+            &format!("{}/release/{}", prefix, target_image),
+            "ubuntu",
+            &None,
+            &packages,
+            &prefix,
+            false,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let prefix = get_distrobox_boost_test_image_prefix();
+        build(&runtime, "dev", &["git", "vim"]);
+        let old_vim_layer = runtime
+            .list_images_by_prefix(&prefix)
+            .unwrap()
+            .into_iter()
+            .find(|tag| tag.contains(":pkg2-"))
+            .unwrap();
+        // vim is replaced, only its layer is not used anymore
+        build(&runtime, "dev", &["git", "htop"]);
+        let builder_count = runtime
+            .list_images_by_prefix(&format!("{}/builder", prefix))
+            .unwrap()
+            .len();
+
+        assert_eq!(
+            collect_garbage(&runtime, &prefix, &[], Some(1), false).unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(collect_garbage(
+            &runtime,
+            &prefix,
+            std::slice::from_ref(&old_vim_layer),
+            None,
+            true
+        )
+        .unwrap()
+        .is_empty());
+        assert_eq!(
+            collect_garbage(&runtime, &prefix, &[], None, true).unwrap(),
+            vec![old_vim_layer.clone()]
+        );
+        assert!(runtime.image(&old_vim_layer).is_some());
+
+        assert_eq!(
+            collect_garbage(&runtime, &prefix, &[], None, false).unwrap(),
+            vec![old_vim_layer.clone()]
+        );
+        assert!(runtime.image(&old_vim_layer).is_none());
+        assert_eq!(
+            runtime
+                .list_images_by_prefix(&format!("{}/builder", prefix))
+                .unwrap()
+                .len(),
+            builder_count - 1
+        );
+        let release = runtime.image(&format!("{}/release/dev", prefix)).unwrap();
+        assert!(release
+            .commands
+            .contains(&"apt-get install -y htop".to_string()));
+    }
+}
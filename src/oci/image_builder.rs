@@ -1,190 +1,418 @@
-use crate::config::get_distrobox_mode;
+use crate::config::{get_distrobox_mode, get_distrobox_setup_marker};
 use crate::distro::distrobox_requirements::get_distrobox_packages;
-use crate::distro::os_info::parse_os_release;
+use crate::distro::os_info::{parse_os_release, OsRelease};
+use crate::distro::package_diff::{diff_packages, PackageChange};
 use crate::distro::package_manager::*;
-use crate::oci::command_helper::*;
-use crate::utils::command_helper::CommandError;
+use crate::oci::container_command::ContainerCommand;
+use crate::oci::container_runtime::ContainerRuntime;
+use crate::set_similarity::compare_sets;
+use crate::utils::command_helper::{print_with_prefix, CommandError};
+use crate::utils::digest::sha256_hex;
 use crate::utils::mutex_lock::*;
 use lazy_static::lazy_static;
-use std::collections::hash_map::{DefaultHasher, HashMap};
-use std::hash::{Hash, Hasher};
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Label with the id of the image a layer was committed on top of
+pub const PARENT_LAYER_LABEL: &str = "parent_layer";
+
+/// Label with the hash of the Dockerfile and options an image was built from
+pub const DOCKERFILE_KEY_LABEL: &str = "dockerfile_key";
+
+/// Options passed to `build` for `dockerfile://` base images
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DockerfileOptions {
+    pub context: Option<String>,
+    pub build_args: Vec<String>,
+    pub target: Option<String>,
+    pub secrets: Vec<String>,
+}
+
+impl DockerfileOptions {
+    /// `context`, or the directory of the Dockerfile
+    pub fn get_context(&self, dockerfile_path: &str) -> String {
+        match &self.context {
+            Some(context) => context.clone(),
+            None => match Path::new(dockerfile_path).parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => {
+                    parent.to_string_lossy().to_string()
+                }
+                _ => ".".to_string(),
+            },
+        }
+    }
+
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        for build_arg in &self.build_args {
+            args.extend(["--build-arg".to_string(), build_arg.clone()]);
+        }
+        if let Some(target) = &self.target {
+            args.extend(["--target".to_string(), target.clone()]);
+        }
+        for secret in &self.secrets {
+            args.extend(["--secret".to_string(), secret.clone()]);
+        }
+        args
+    }
+}
+
+fn dockerfile_cache_key(dockerfile: &[u8], context: &str, args: &[String]) -> String {
+    // files in the context are not hashed, only the Dockerfile and how it is built
+    let mut data = dockerfile.to_vec();
+    for part in std::iter::once(context).chain(args.iter().map(String::as_str)) {
+        data.push(b'\n');
+        data.extend_from_slice(part.as_bytes());
+    }
+    sha256_hex(&data)
+}
+
+/// `container_name` as part of an image name, the parent of containers with a
+/// `dockerfile://` image is named after the Dockerfile path: lowercase letters and
+/// digits joined by "_", with a short hash of the full name so paths stay apart
+pub fn get_image_name_part(container_name: &str) -> String {
+    let Some(path) = container_name.strip_prefix("dockerfile://") else {
+        return container_name.to_string();
+    };
+    let mut name_part = String::new();
+    for c in path.to_lowercase().chars() {
+        if c.is_ascii_alphanumeric() {
+            name_part.push(c);
+        } else if !name_part.is_empty() && !name_part.ends_with('_') {
+            name_part.push('_');
+        }
+    }
+    let hash = sha256_hex(container_name.as_bytes());
+    format!("{}-{}", name_part.trim_end_matches('_'), &hash[..8])
+        .trim_start_matches('-')
+        .to_string()
+}
+
+/// Image that a `dockerfile://` base of `container_name` is built to
+pub fn get_dockerfile_image_name(container_name: &str, image_prefix: &str) -> String {
+    format!(
+        "{}/dockerfile/{}",
+        image_prefix,
+        get_image_name_part(container_name)
+    )
+}
+
+/// Build a `dockerfile://` base image with `options`, any other base image is returned as is
 pub fn pre_build_image(
-    container_runner: &str,
-    target_image: &str,
+    runtime: &dyn ContainerRuntime,
+    container_name: &str,
     base_image: &str,
     image_prefix: &str,
+    options: &DockerfileOptions,
 ) -> Result<String, CommandError> {
-    if base_image.starts_with("dockerfile://") {
-        println!("Build dockerfile: {}", &base_image);
-        let image_name = format!("{}/dockerfile/{}", &image_prefix, &target_image);
-        let dockerfile_path = &base_image[12..];
-        let dockerfile_content_path = Path::new(dockerfile_path)
-            .parent()
-            .unwrap()
-            .to_str()
-            .unwrap();
-        let _ = build_image_from_dockerfile_simple(
-            container_runner,
-            &image_name,
-            &dockerfile_path,
-            dockerfile_content_path,
-        )?;
-        Ok(image_name.to_string())
-    } else {
-        Ok(base_image.to_string())
-    }
+    let Some(dockerfile_path) = base_image.strip_prefix("dockerfile://") else {
+        return Ok(base_image.to_string());
+    };
+    print_with_prefix(&format!("Build dockerfile: {}", &base_image));
+    let image_name = get_dockerfile_image_name(container_name, image_prefix);
+    let context = options.get_context(dockerfile_path);
+    let mut args = options.to_args();
+    let cache_key = dockerfile_cache_key(&fs::read(dockerfile_path)?, &context, &args);
+    let filter = format!("label={}={}", DOCKERFILE_KEY_LABEL, cache_key);
+    GLOBAL_SYNC_MAP.execute(filter.clone(), || -> Result<(), CommandError> {
+        if let Some(image_id) = runtime.find_images(&[&filter])?.first() {
+            print_with_prefix(&format!("Dockerfile unchanged, reuse image: {}", image_id));
+            runtime.tag_image(image_id, &image_name)?;
+        } else {
+            args.extend([
+                "--label".to_string(),
+                format!("{}={}", DOCKERFILE_KEY_LABEL, cache_key),
+            ]);
+            runtime.build_image_from_dockerfile_with_args(
+                &image_name,
+                dockerfile_path,
+                &context,
+                &args.iter().map(AsRef::as_ref).collect::<Vec<&str>>(),
+            )?;
+        }
+        Ok(())
+    })?;
+    Ok(image_name)
 }
 
+/// Build `target_image` from `base_image`, a `dockerfile://` base has to be built with
+/// `pre_build_image` first so its options are not lost
 pub fn build_image(
-    container_runner: &str,
+    runtime: &dyn ContainerRuntime,
     target_image: &str,
     base_image: &str,
     request_package_manager: &Option<String>,
-    packages: &Vec<String>,
+    packages: &[String],
     image_prefix: &str,
     distrobox_mode: bool,
 ) -> Result<String, CommandError> {
-    let base_image = pre_build_image(container_runner, target_image, base_image, image_prefix)?;
-    let cmd = "cat /etc/os-release".to_string();
-    let output = run_container(container_runner, "", &base_image, &cmd, true)?;
-    let distro_info = parse_os_release(&output.stdout).unwrap();
+    if base_image.starts_with("dockerfile://") {
+        return Err(CommandError {
+            stdout: String::new(),
+            stderr: format!("{} has to be built with pre_build_image first", base_image),
+            status: Some(1),
+            inner: None,
+        });
+    }
+    let os_release = get_image_os_release(runtime, base_image)?;
     let package_manager = request_package_manager
         .clone()
-        .unwrap_or(get_package_manager(&distro_info.0, &distro_info.1));
+        .unwrap_or(get_package_manager(&os_release));
+    validate_package_names(&package_manager, packages)?;
     let slim_image_name = format!(
         "{}/builder/{}",
         image_prefix,
         target_image.replace(":", "-").replace("/", "_")
     );
 
-    let mut filter_map = HashMap::new();
-    filter_map.insert("image".to_string(), base_image.to_string());
-
-    fn _run_and_commit_image(
-        runner: &str,
-        cmd: &str,
-        target_image: &str,
-        base_image: &str,
-        filter_map: &HashMap<String, String>,
-        realtime_output: bool,
-    ) -> Result<(), CommandError> {
-        let filter_map = filter_map
+    let mut label_map = HashMap::new();
+    label_map.insert("image".to_string(), base_image.to_string());
+
+    let _run_and_commit_image = |cmd: &ContainerCommand,
+                                 step: &str,
+                                 base_image: &str,
+                                 label_map: &HashMap<String, String>,
+                                 realtime_output: bool|
+     -> Result<String, CommandError> {
+        let parent_id = runtime.inspect_image(base_image, ".Id")?;
+        let cache_key = layer_cache_key(parent_id.trim(), &cmd.to_string());
+        let target_image = format!("{}:{}-{}", slim_image_name, step, cache_key);
+        let filter_map = HashMap::from([("cache_key".to_string(), Some(cache_key))]);
+        let mut instruction_map: HashMap<String, Option<String>> = label_map
             .iter()
             .map(|(k, v)| (k.clone(), Some(v.clone())))
             .collect();
+        instruction_map.extend(filter_map.clone());
+        instruction_map.insert(
+            PARENT_LAYER_LABEL.to_string(),
+            Some(parent_id.trim().to_string()),
+        );
         run_and_commit_image(&ContainerData {
-            runner,
+            runtime,
             cmd,
-            target_image,
+            target_image: &target_image,
             base_image,
-            filters: &get_filter_vec(&filter_map)
+            filters: get_filter_vec(&filter_map)
                 .iter()
                 .map(AsRef::as_ref)
                 .collect::<Vec<&str>>()
                 .as_slice(),
-            instructions: &get_instructions(&filter_map)
+            instructions: get_instructions(&instruction_map)
                 .iter()
                 .map(AsRef::as_ref)
                 .collect::<Vec<&str>>()
                 .as_slice(),
             realtime_output,
-        })
-    }
+        })?;
+        Ok(target_image)
+    };
 
     let cmd = generate_update_command(&package_manager);
-    println!("Update image: {}", slim_image_name);
-    let updated_image = format!("{}:db_updated", slim_image_name);
-    filter_map.insert("status".to_string(), "db_update".to_string());
-    _run_and_commit_image(
-        container_runner,
-        &cmd,
-        &updated_image,
-        &base_image,
-        &filter_map,
-        true,
-    )?;
-    println!("Updated image: {}", updated_image);
+    print_with_prefix(&format!("Update image: {}", slim_image_name));
+    label_map.insert("status".to_string(), "db_update".to_string());
+    let updated_image = _run_and_commit_image(&cmd, "db_updated", base_image, &label_map, true)?;
+    print_with_prefix(&format!("Updated image: {}", updated_image));
     let mut basic_package_image = updated_image.clone();
     if get_distrobox_mode() {
-        println!("Install distrobox requirements");
-        let packages = get_distrobox_packages(&distro_info.0, &distro_info.1);
-        let cmd = generate_install_command(&package_manager, &packages);
-        basic_package_image = format!("{}:distrobox_pre", slim_image_name);
-        filter_map.insert("status".to_string(), "distrobox_pre_install".to_string());
-        filter_map.insert("packages0".to_string(), packages.join(";"));
-        _run_and_commit_image(
-            container_runner,
-            &cmd,
-            &basic_package_image,
-            &updated_image,
-            &filter_map,
-            true,
-        )?;
+        print_with_prefix("Install distrobox requirements");
+        let requirements = get_distrobox_packages(&os_release);
+        let cmd = generate_install_command_with_optional(
+            &package_manager,
+            &requirements.packages,
+            &requirements.optional_packages,
+            &requirements.optional_scripts,
+        );
+        label_map.insert("status".to_string(), "distrobox_pre_install".to_string());
+        label_map.insert("packages0".to_string(), requirements.packages.join(";"));
+        basic_package_image =
+            _run_and_commit_image(&cmd, "distrobox_pre", &updated_image, &label_map, true)?;
     }
-    println!(
+    print_with_prefix(&format!(
         "Initial image name(with updated tag): {}",
         basic_package_image
-    );
+    ));
     if !packages.is_empty() {
+        // only images on top of this exact setup layer can be reused
+        let setup_layer = runtime
+            .inspect_image(&basic_package_image, ".Id")?
+            .trim()
+            .to_string();
+        label_map.insert("setup_layer".to_string(), setup_layer.clone());
+        label_map.insert("status".to_string(), "package_install".to_string());
+        let filter_map = HashMap::from([
+            ("status".to_string(), Some("package_install".to_string())),
+            ("setup_layer".to_string(), Some(setup_layer.clone())),
+            ("package1".to_string(), None),
+        ]);
         let mut installed_packages = Vec::new();
+        let mut requested_packages: HashSet<String> = packages.iter().cloned().collect();
+        if let Some(packages0) = label_map.get("packages0") {
+            requested_packages.extend(package_tag_to_list(packages0));
+        }
+        if let Some(closest_image_id) =
+            get_closest_image_id(runtime, &filter_map, &requested_packages)?
+        {
+            installed_packages =
+                package_tag_to_list(&get_image_label(runtime, &closest_image_id, "package1")?);
+            print_with_prefix(&format!(
+                "Start from closest image {}\nPackages: {}",
+                closest_image_id,
+                installed_packages.join(" ")
+            ));
+            basic_package_image = closest_image_id;
+        }
         for package in packages {
-            installed_packages.push(package);
-            let package_label = installed_packages
-                .iter()
-                .map(|s| s.to_string())
-                .collect::<Vec<String>>()
-                .join(";");
-            let cmd = generate_install_command(&package_manager, &vec![package.as_str()]);
-            let in_seceonds = get_seconds();
-            let package_installed_image = format!(
-                "{}:pkg{}-{}{}",
-                installed_packages.len(),
-                slim_image_name,
-                hash(&package_label),
-                in_seceonds
-            );
-            filter_map.insert("status".to_string(), "package_install".to_string());
-            filter_map.insert("package1".to_string(), package_label);
-            _run_and_commit_image(
-                container_runner,
+            if installed_packages.contains(package) {
+                continue;
+            }
+            installed_packages.push(package.clone());
+            let package_label = installed_packages.join(";");
+            let cmd = generate_install_command(&package_manager, &[package.as_str()]);
+            label_map.insert("package1".to_string(), package_label);
+            let package_installed_image = _run_and_commit_image(
                 &cmd,
-                &package_installed_image,
+                &format!("pkg{}", installed_packages.len()),
                 &basic_package_image,
-                &filter_map,
+                &label_map,
                 true,
             )?;
-            println!(
+            print_with_prefix(&format!(
                 "Package installed at {}\nPackages: {}",
                 package_installed_image, package
-            );
+            ));
             basic_package_image = package_installed_image;
         }
     }
 
-    if distrobox_mode {
-        println!("Touch /run/.containersetupdone for distrobox");
-        let distrobox_setup_tag_image = format!("{}:mark_distrobox_setup_done", slim_image_name);
-        let cmd = "touch /run/.containersetupdone";
-        filter_map.insert("status".to_string(), "distrobox_setup".to_string());
-        _run_and_commit_image(
-            container_runner,
+    let setup_marker = get_distrobox_setup_marker().filter(|_| distrobox_mode);
+    if let Some(setup_marker) = setup_marker {
+        print_with_prefix(&format!("Touch {} for distrobox", setup_marker));
+        let cmd = ContainerCommand::exec(&["touch", setup_marker.as_str()]);
+        label_map.insert("status".to_string(), "distrobox_setup".to_string());
+        basic_package_image = _run_and_commit_image(
             &cmd,
-            &distrobox_setup_tag_image,
+            "mark_distrobox_setup_done",
             &basic_package_image,
-            &filter_map,
+            &label_map,
             false,
         )?;
-        basic_package_image = distrobox_setup_tag_image;
     }
 
-    println!("Final snap image name: {}", basic_package_image);
-    tag_image(container_runner, &basic_package_image, target_image)?;
+    print_with_prefix(&format!("Final snap image name: {}", basic_package_image));
+    runtime.tag_image(&basic_package_image, target_image)?;
     Ok(target_image.to_string())
 }
 
+/// Upgrade all packages of `image_name` in a new layer tagged as `image_name`,
+/// returns the packages whose version changed
+pub fn update_image(
+    runtime: &dyn ContainerRuntime,
+    image_name: &str,
+    request_package_manager: &Option<String>,
+) -> Result<Vec<PackageChange>, CommandError> {
+    let package_manager = get_image_package_manager(runtime, image_name, request_package_manager)?;
+    let cmd = generate_upgrade_command(&package_manager);
+    if cmd.is_empty() {
+        return Err(CommandError {
+            stdout: String::new(),
+            stderr: format!("Unsupported package manager for {}", image_name),
+            status: Some(1),
+            inner: None,
+        });
+    }
+
+    let before = list_image_packages(runtime, image_name, &package_manager)?;
+    print_with_prefix(&format!("Upgrade image: {}", image_name));
+    let parent_id = runtime.inspect_image(image_name, ".Id")?;
+    let label_map = HashMap::from([
+        ("status".to_string(), Some("package_upgrade".to_string())),
+        (
+            PARENT_LAYER_LABEL.to_string(),
+            Some(parent_id.trim().to_string()),
+        ),
+    ]);
+    create_new_image(&ContainerData {
+        runtime,
+        cmd: &cmd,
+        base_image: image_name,
+        target_image: image_name,
+        filters: &[],
+        instructions: get_instructions(&label_map)
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<&str>>()
+            .as_slice(),
+        realtime_output: true,
+    })?;
+    let after = list_image_packages(runtime, image_name, &package_manager)?;
+    Ok(diff_packages(&before, &after))
+}
+
+/// Packages added, removed or changed from `image_a` to `image_b`
+pub fn diff_images(
+    runtime: &dyn ContainerRuntime,
+    image_a: &str,
+    image_b: &str,
+    request_package_manager: &Option<String>,
+) -> Result<Vec<PackageChange>, CommandError> {
+    let package_manager = get_image_package_manager(runtime, image_a, request_package_manager)?;
+    let before = list_image_packages(runtime, image_a, &package_manager)?;
+    let after = list_image_packages(runtime, image_b, &package_manager)?;
+    Ok(diff_packages(&before, &after))
+}
+
+pub fn list_image_packages(
+    runtime: &dyn ContainerRuntime,
+    image_name: &str,
+    package_manager: &str,
+) -> Result<Vec<(String, String)>, CommandError> {
+    let cmd = generate_list_packages_command(package_manager);
+    let output = runtime.run_container("", image_name, &cmd, false)?;
+    Ok(parse_list_packages_command_output(
+        package_manager,
+        &output.stdout,
+    ))
+}
+
+/// `request_package_manager` if set, otherwise the one of the image's distro
+pub fn get_image_package_manager(
+    runtime: &dyn ContainerRuntime,
+    image_name: &str,
+    request_package_manager: &Option<String>,
+) -> Result<String, CommandError> {
+    if let Some(package_manager) = request_package_manager {
+        return Ok(package_manager.clone());
+    }
+    let os_release = get_image_os_release(runtime, image_name)?;
+    Ok(get_package_manager(&os_release))
+}
+
+pub fn get_image_os_release(
+    runtime: &dyn ContainerRuntime,
+    image_name: &str,
+) -> Result<OsRelease, CommandError> {
+    let output = runtime.run_container(
+        "",
+        image_name,
+        &ContainerCommand::exec(&["cat", "/etc/os-release"]),
+        true,
+    )?;
+    parse_os_release(&output.stdout).ok_or_else(|| CommandError {
+        stdout: output.stdout,
+        stderr: format!("Failed to parse /etc/os-release of {}", image_name),
+        status: Some(1),
+        inner: None,
+    })
+}
+
+/// Identify a layer by what it is built from and what runs in it, so the same
+/// parent image and command always map to the same cached layer
+fn layer_cache_key(parent_image_id: &str, cmd: &str) -> String {
+    sha256_hex(format!("{}\n{}", parent_image_id, cmd).as_bytes())
+}
+
 fn get_filter_vec(filter_map: &HashMap<String, Option<String>>) -> Vec<String> {
     let mut filter_vec = Vec::new();
     for (key, value) in filter_map {
@@ -215,8 +443,8 @@ lazy_static! {
 }
 
 pub struct ContainerData<'a> {
-    pub runner: &'a str,
-    pub cmd: &'a str,
+    pub runtime: &'a dyn ContainerRuntime,
+    pub cmd: &'a ContainerCommand,
     pub base_image: &'a str,
     pub target_image: &'a str,
     pub filters: &'a [&'a str],
@@ -225,73 +453,79 @@ pub struct ContainerData<'a> {
 }
 
 fn package_tag_to_list(tag_string: &str) -> Vec<String> {
-    tag_string.split(";").map(|s| s.to_string()).collect()
+    tag_string
+        .split(";")
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn get_image_label(
+    runtime: &dyn ContainerRuntime,
+    image_name: &str,
+    label: &str,
+) -> Result<String, CommandError> {
+    let value = runtime.inspect_image(image_name, &format!(".Config.Labels.{}", label))?;
+    Ok(value.trim().to_string())
 }
 
 fn get_image_packages(
-    container_runner: &str,
+    runtime: &dyn ContainerRuntime,
     image_name: &str,
-) -> Result<Vec<String>, CommandError> {
-    let package_inspect_format = "{{.Labels.packages}}";
-    let packages_tag = format!(
-        "{};{}",
-        inspect_image(
-            container_runner,
-            image_name,
-            &package_inspect_format.replace("packages", "packages0"),
-        )?,
-        inspect_image(
-            container_runner,
-            image_name,
-            &package_inspect_format.replace("packages", "packages1"),
-        )?
-    );
-    Ok(package_tag_to_list(&packages_tag))
+) -> Result<HashSet<String>, CommandError> {
+    let mut packages = HashSet::new();
+    for label in ["packages0", "package1"] {
+        packages.extend(package_tag_to_list(&get_image_label(
+            runtime, image_name, label,
+        )?));
+    }
+    Ok(packages)
 }
 
+/// Find the image whose packages cover most of `packages` without extra ones,
+/// `None` filters in `filter_map` only require the label to exist
 fn get_closest_image_id(
-    container_runner: &str,
+    runtime: &dyn ContainerRuntime,
     filter_map: &HashMap<String, Option<String>>,
-) -> Option<String> {
-    let score_keys = vec!["packages0", "package1"];
-    let filter_map = filter_map
-        .iter()
-        .map(|(k, v)| {
-            (
-                k.clone(),
-                if score_keys.contains(&k.as_str()) {
-                    None
-                } else {
-                    v.clone()
-                },
-            )
-        })
-        .collect();
-    let image_id_list = find_images(
-        container_runner,
-        &get_filter_vec(&filter_map)
+    packages: &HashSet<String>,
+) -> Result<Option<String>, CommandError> {
+    let mut image_id_list = runtime.find_images(
+        get_filter_vec(filter_map)
             .iter()
             .map(AsRef::as_ref)
             .collect::<Vec<&str>>()
             .as_slice(),
-    )
-    .unwrap();
-    let mut image_id_list = image_id_list.to_vec();
+    )?;
     image_id_list.sort();
-    image_id_list.reverse();
-    image_id_list.first().cloned()
+    image_id_list.dedup();
+
+    let mut closest: Option<(i32, String)> = None;
+    for image_id in image_id_list {
+        let image_packages = get_image_packages(runtime, &image_id)?;
+        let similarity = compare_sets(&image_packages, packages, 1, 1);
+        if similarity.less > 0 {
+            continue;
+        }
+        if closest
+            .as_ref()
+            .is_none_or(|(more, _)| similarity.more > *more)
+        {
+            closest = Some((similarity.more, image_id));
+        }
+    }
+    Ok(closest.map(|(_, image_id)| image_id))
 }
 
 fn recommit_image(
-    container_runner: &str,
+    runtime: &dyn ContainerRuntime,
     target_image: &str,
     image_id_list: &[String],
 ) -> Result<(), CommandError> {
     let image_id = image_id_list.first().unwrap();
-    println!("Image {} already exists", image_id);
-    tag_image(container_runner, image_id, target_image)?;
-    println!("Tagged image: {} by {}", target_image, image_id);
-    return Ok(());
+    print_with_prefix(&format!("Image {} already exists", image_id));
+    runtime.tag_image(image_id, target_image)?;
+    print_with_prefix(&format!("Tagged image: {} by {}", target_image, image_id));
+    Ok(())
 }
 
 fn create_new_image(data: &ContainerData) -> Result<(), CommandError> {
@@ -305,33 +539,28 @@ fn create_new_image(data: &ContainerData) -> Result<(), CommandError> {
         get_seconds()
     );
 
-    if !check_container_exists(data.runner, &container_name)? {
-        println!("Running container: {}", &container_name);
-        let output = run_container(
-            data.runner,
+    if !data.runtime.check_container_exists(&container_name)? {
+        print_with_prefix(&format!("Running container: {}", &container_name));
+        let output = data.runtime.run_container(
             &container_name,
-            &data.base_image,
-            &data.cmd,
+            data.base_image,
+            data.cmd,
             data.realtime_output,
         )?;
         if let Some(status) = output.status {
-            println!("status: {}", status);
+            print_with_prefix(&format!("status: {}", status));
         }
     } else {
-        println!("Container {} already exists", &container_name);
+        print_with_prefix(&format!("Container {} already exists", &container_name));
     }
 
-    println!(
+    print_with_prefix(&format!(
         "Commit image: {} by {}",
         &data.target_image, &container_name
-    );
-    commit_container(
-        data.runner,
-        &container_name,
-        &data.target_image,
-        data.instructions,
-    )?;
-    remove_container(data.runner, &container_name)?;
+    ));
+    data.runtime
+        .commit_container(&container_name, data.target_image, data.instructions)?;
+    data.runtime.remove_container(&container_name)?;
 
     Ok(())
 }
@@ -339,25 +568,19 @@ fn create_new_image(data: &ContainerData) -> Result<(), CommandError> {
 fn run_and_commit_image(data: &ContainerData) -> Result<(), CommandError> {
     let key = data.filters.join(";");
     GLOBAL_SYNC_MAP.execute(key, || -> Result<(), CommandError> {
-        let image_id_list = find_images(data.runner, data.filters)?;
+        let image_id_list = data.runtime.find_images(data.filters)?;
 
         if !image_id_list.is_empty() {
-            recommit_image(data.runner, data.target_image, &image_id_list)?;
+            recommit_image(data.runtime, data.target_image, &image_id_list)?;
         } else {
-            create_new_image(&data)?;
+            create_new_image(data)?;
         };
 
         Ok(())
     })
 }
 
-fn hash<T: Hash>(t: T) -> u8 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    (s.finish() & 0xffff) as u8
-}
-
-fn get_seconds() -> u64 {
+pub fn get_seconds() -> u64 {
     let start = SystemTime::now();
     let since_the_epoch = start
         .duration_since(UNIX_EPOCH)
@@ -368,19 +591,18 @@ fn get_seconds() -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{
-        get_container_manager, get_distrobox_boost_test_image_prefix, set_distrobox_mode,
-    };
+    use crate::config::get_distrobox_boost_test_image_prefix;
+    use crate::oci::fake_runtime::{FakeRuntime, UBUNTU_OS_RELEASE};
 
     #[test]
     fn test_build_image() {
-        let container_runner = &get_container_manager();
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
         let image_name = "test_image";
         let base_image = "ubuntu";
         let packages = vec!["bash".to_string(), "pacman".to_string()];
 
         let result = build_image(
-            container_runner,
+            &runtime,
             image_name,
             base_image,
             &None,
@@ -389,27 +611,31 @@ mod tests {
             false,
         );
 
-        match result {
-            Ok(image_name) => {
-                println!("Build image name: {}", image_name);
-                assert_eq!(image_name, "test_image");
-            }
-            Err(e) => {
-                println!("Error building image: {:?}", e);
-                assert!(false, "Error building image");
-            }
+        assert_eq!(result.unwrap(), "test_image");
+        assert!(runtime.image("test_image").is_some());
+    }
+
+    fn assert_commands_run(runtime: &FakeRuntime, image_name: &str, commands: &[&str]) {
+        let image = runtime.image(image_name).unwrap();
+        for command in commands {
+            assert!(
+                image.commands.iter().any(|c| c == command),
+                "{} not run for {}",
+                command,
+                image_name
+            );
         }
     }
 
     #[test]
     fn test_build_image_valid() {
-        let container_runner = &get_container_manager();
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
         let image_name = "test_build_image_valid";
         let base_image = "ubuntu";
         let packages = vec!["fish".to_string(), "htop".to_string()];
 
         let result = build_image(
-            container_runner,
+            &runtime,
             image_name,
             base_image,
             &None,
@@ -418,38 +644,27 @@ mod tests {
             true,
         )
         .unwrap();
-        println!("Final image name: {}", result);
-
-        // Test if 'fish' and 'top' commands exist
-        let commands = vec!["fish", "htop"];
-        for command in commands {
-            let cmd = format!("command -v {}", command);
-            let result = run_container(container_runner, "", &result, &cmd, true);
-            assert!(result.is_ok(), "Error running command {}", command);
-        }
 
-        let result = run_container(
-            container_runner,
-            "",
+        assert_commands_run(
+            &runtime,
             &result,
-            "ls /run/.containersetupdone",
-            true,
-        );
-        assert!(
-            result.is_ok(),
-            "/run/.containersetupdone not found at {}",
-            image_name
+            &[
+                "apt-get install -y fish",
+                "apt-get install -y htop",
+                "touch /run/.containersetupdone",
+            ],
         );
     }
+
     #[test]
     fn test_build_image_valid_no_distrobox_mode() {
-        let container_runner = &get_container_manager();
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
         let image_name = "test_build_image_valid_no_distrobox_mode";
         let base_image = "ubuntu";
         let packages = vec!["fish".to_string(), "htop".to_string()];
 
         let result = build_image(
-            container_runner,
+            &runtime,
             image_name,
             base_image,
             &None,
@@ -458,39 +673,29 @@ mod tests {
             false,
         )
         .unwrap();
-        println!("Final image name: {}", result);
-
-        // Test if 'fish' and 'top' commands exist
-        let commands = vec!["fish", "htop"];
-        for command in commands {
-            let cmd = format!("command -v {}", command);
-            let result = run_container(container_runner, "", &result, &cmd, false);
-            assert!(result.is_ok(), "Error running command {}", command);
-        }
 
-        let result = run_container(
-            container_runner,
-            "",
+        assert_commands_run(
+            &runtime,
             &result,
-            "ls /run/.containersetupdone",
-            true,
+            &["apt-get install -y fish", "apt-get install -y htop"],
         );
+        let image = runtime.image(&result).unwrap();
         assert!(
-            result.is_err(),
-            "/run/.containersetupdone found at {} in no distrobox mode",
+            !image.commands.iter().any(|c| c.starts_with("touch ")),
+            "setup marker created at {} in no distrobox mode",
             image_name
         );
     }
+
     #[test]
     fn test_build_image_valid_distrobox_mode() {
-        let container_runner = &get_container_manager();
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
         let image_name = "test_image_distrobox_mode";
         let base_image = "ubuntu";
         let packages = vec![];
 
-        set_distrobox_mode(true);
         let result = build_image(
-            container_runner,
+            &runtime,
             image_name,
             base_image,
             &None,
@@ -499,25 +704,18 @@ mod tests {
             true,
         )
         .unwrap();
-        println!("Final image name: {}", result);
 
-        let result = run_container(
-            container_runner,
-            "",
-            &result,
-            "ls /run/.containersetupdone",
-            true,
-        );
-        assert!(
-            result.is_ok(),
-            "/run/.containersetupdone not found at {}",
-            image_name
+        let image = runtime.image(&result).unwrap();
+        assert_eq!(
+            image.commands.last().map(String::as_str),
+            Some("touch /run/.containersetupdone")
         );
+        assert_eq!(image.labels["status"], "distrobox_setup");
     }
 
     #[test]
     fn test_create_new_image() {
-        let container_runner = &get_container_manager();
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
         let target_image = "test_create_new_image";
         let base_image = "ubuntu";
         let mut filter_map = HashMap::new();
@@ -526,19 +724,18 @@ mod tests {
             Some("test_create_new_image".to_string()),
         );
 
-        let _ = remove_image(container_runner, &target_image);
         let image_filter = get_filter_vec(&filter_map);
 
         let result = create_new_image(&ContainerData {
-            runner: container_runner,
-            cmd: "touch /test_create_new_image",
+            runtime: &runtime,
+            cmd: &ContainerCommand::exec(&["touch", "/test_create_new_image"]),
             base_image,
             target_image,
             filters: &image_filter
                 .iter()
                 .map(AsRef::as_ref)
                 .collect::<Vec<&str>>(),
-            instructions: &get_instructions(&filter_map)
+            instructions: get_instructions(&filter_map)
                 .iter()
                 .map(AsRef::as_ref)
                 .collect::<Vec<&str>>()
@@ -546,18 +743,414 @@ mod tests {
             realtime_output: true,
         });
 
-        let filter_images = find_images(
-            container_runner,
-            &image_filter
+        assert!(result.is_ok());
+        let filter_images = runtime
+            .find_images(
+                image_filter
+                    .iter()
+                    .map(AsRef::as_ref)
+                    .collect::<Vec<&str>>()
+                    .as_slice(),
+            )
+            .unwrap();
+        assert!(!filter_images.is_empty());
+        assert_eq!(
+            runtime.image(target_image).unwrap().commands,
+            ["touch /test_create_new_image"]
+        );
+        assert!(runtime.container_names().is_empty());
+    }
+
+    #[test]
+    fn test_build_image_fake_layers() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let packages = vec!["fish".to_string(), "htop".to_string()];
+
+        let result = build_image(
+            &runtime,
+            "test_build_image_fake_layers",
+            "ubuntu",
+            &None,
+            &packages,
+            &get_distrobox_boost_test_image_prefix(),
+            true,
+        )
+        .unwrap();
+        assert_eq!(result, "test_build_image_fake_layers");
+
+        let image = runtime.image(&result).unwrap();
+        assert_eq!(image.commands.len(), 5);
+        assert_eq!(image.commands[0], "apt-get update");
+        assert!(image.commands[1].starts_with("apt-get install -y apt-utils"));
+        assert_eq!(image.commands[2], "apt-get install -y fish");
+        assert_eq!(image.commands[3], "apt-get install -y htop");
+        assert_eq!(image.commands[4], "touch /run/.containersetupdone");
+        assert_eq!(image.labels["status"], "distrobox_setup");
+
+        // every step is its own layer on top of the previous one
+        let mut layer = image;
+        for _ in 0..5 {
+            layer = runtime.image(layer.parent.as_ref().unwrap()).unwrap();
+        }
+        assert_eq!(Some(layer.id), runtime.image("ubuntu").map(|i| i.id));
+        assert!(runtime.container_names().is_empty());
+    }
+
+    #[test]
+    fn test_build_image_fake_cache_hit() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let packages = vec!["fish".to_string()];
+        let build = |target_image: &str| {
+            build_image(
+                &runtime,
+                target_image,
+                "ubuntu",
+                &None,
+                &packages,
+                &get_distrobox_boost_test_image_prefix(),
+                true,
+            )
+            .unwrap()
+        };
+
+        let first = build("test_build_image_fake_cache_hit");
+        let run_count = runtime.run_commands().len();
+        let second = build("test_build_image_fake_cache_hit_again");
+
+        // only the os-release probe runs again, all layers come from the cache
+        assert_eq!(
+            runtime.run_commands()[run_count..],
+            ["cat /etc/os-release".to_string()]
+        );
+        assert_eq!(
+            runtime.image(&first).unwrap().id,
+            runtime.image(&second).unwrap().id
+        );
+    }
+
+    #[test]
+    fn test_layer_cache_key() {
+        let key = layer_cache_key("sha256:1234", "apt-get install -y fish");
+        assert_eq!(key.len(), 64);
+        assert_eq!(
+            key,
+            layer_cache_key("sha256:1234", "apt-get install -y fish")
+        );
+        assert_ne!(
+            key,
+            layer_cache_key("sha256:5678", "apt-get install -y fish")
+        );
+        assert_ne!(
+            key,
+            layer_cache_key("sha256:1234", "apt-get install -y htop")
+        );
+    }
+
+    #[test]
+    fn test_build_image_fake_cache_key_tags() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let build = |packages: &[String]| {
+            build_image(
+                &runtime,
+                "test_build_image_fake_cache_key_tags",
+                "ubuntu",
+                &None,
+                packages,
+                &get_distrobox_boost_test_image_prefix(),
+                false,
+            )
+            .unwrap();
+            runtime
+                .image("test_build_image_fake_cache_key_tags")
+                .unwrap()
+        };
+
+        let fish = build(&["fish".to_string()]);
+        let htop = build(&["htop".to_string()]);
+        // same step index with different packages must not share a layer
+        assert_ne!(fish.id, htop.id);
+        assert_ne!(fish.labels["cache_key"], htop.labels["cache_key"]);
+        assert_eq!(fish.parent, htop.parent);
+
+        let tag = runtime
+            .calls()
+            .into_iter()
+            .find(|call| call.starts_with("commit") && call.contains(":pkg1-"))
+            .unwrap();
+        assert!(tag.ends_with(&fish.labels["cache_key"]));
+    }
+
+    #[test]
+    fn test_pre_build_image_fake_dockerfile_cache() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let dir = std::env::temp_dir().join(format!(
+            "distrobox_boost_test_dockerfile_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let dockerfile = dir.join("Containerfile");
+        let base_image = format!("dockerfile://{}", dockerfile.display());
+        let options = DockerfileOptions {
+            build_args: vec!["VERSION=1".to_string()],
+            target: Some("runtime".to_string()),
+            ..Default::default()
+        };
+        let pre_build = |options: &DockerfileOptions| {
+            pre_build_image(&runtime, "app", &base_image, "test", options).unwrap()
+        };
+        let build_count = || {
+            runtime
+                .calls()
                 .iter()
-                .map(AsRef::as_ref)
-                .collect::<Vec<&str>>()
-                .as_slice(),
+                .filter(|call| call.starts_with("build"))
+                .count()
+        };
+
+        fs::write(&dockerfile, "FROM ubuntu\nRUN true\n").unwrap();
+        assert_eq!(pre_build(&options), "test/dockerfile/app");
+        let build = runtime.calls().into_iter().last().unwrap();
+        assert!(build.starts_with(&format!(
+            "build test/dockerfile/app {} {} --build-arg VERSION=1 --target runtime --label dockerfile_key=",
+            dockerfile.display(),
+            dir.display()
+        )));
+        let first = runtime.image("test/dockerfile/app").unwrap();
+
+        // unchanged Dockerfile and options reuse the image
+        pre_build(&options);
+        assert_eq!(build_count(), 1);
+        assert_eq!(runtime.image("test/dockerfile/app").unwrap().id, first.id);
+
+        pre_build(&DockerfileOptions::default());
+        assert_eq!(build_count(), 2);
+        fs::write(&dockerfile, "FROM ubuntu\nRUN false\n").unwrap();
+        pre_build(&options);
+        assert_eq!(build_count(), 3);
+        assert_ne!(runtime.image("test/dockerfile/app").unwrap().id, first.id);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn is_valid_name_part(name: &str) -> bool {
+        // a path component of an OCI repository name
+        name.split(['_', '-']).all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        })
+    }
+
+    #[test]
+    fn test_get_image_name_part() {
+        assert_eq!(get_image_name_part("ubuntu:latest"), "ubuntu:latest");
+        for path in [
+            "dockerfile://./Containerfile",
+            "dockerfile://../x",
+            "dockerfile:///home/me/My Apps/Containerfile",
+            "dockerfile://Containerfile#259f212e1cd3",
+            "dockerfile://",
+        ] {
+            let name_part = get_image_name_part(path);
+            assert!(is_valid_name_part(&name_part), "{} -> {}", path, name_part);
+        }
+        assert!(get_image_name_part("dockerfile://./Containerfile").starts_with("containerfile-"));
+        assert!(get_image_name_part("dockerfile://../x").starts_with("x-"));
+        assert!(
+            get_image_name_part("dockerfile:///home/me/My Apps/Containerfile")
+                .starts_with("home_me_my_apps_containerfile-")
+        );
+        // paths that only differ in what is replaced keep different names
+        assert_ne!(
+            get_image_name_part("dockerfile://../x"),
+            get_image_name_part("dockerfile://x")
+        );
+    }
+
+    #[test]
+    fn test_build_image_fake_dockerfile_not_pre_built() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let result = build_image(
+            &runtime,
+            "app",
+            "dockerfile:///src/app/Containerfile",
+            &None,
+            &[],
+            &get_distrobox_boost_test_image_prefix(),
+            false,
+        );
+        assert!(result.unwrap_err().stderr.contains("pre_build_image"));
+        assert!(runtime.calls().is_empty());
+    }
+
+    #[test]
+    fn test_build_image_fake_id_like() {
+        // rolling release without VERSION_ID, only known through ID_LIKE
+        let runtime = FakeRuntime::new().with_image(
+            "manjaro",
+            "NAME=\"Manjaro Linux\"\nID=manjaro\nID_LIKE=arch\nBUILD_ID=rolling\n",
+        );
+        build_image(
+            &runtime,
+            "test_build_image_fake_id_like",
+            "manjaro",
+            &None,
+            &["fish".to_string()],
+            &get_distrobox_boost_test_image_prefix(),
+            false,
         )
         .unwrap();
-        assert!(!filter_images.is_empty());
-        let _ = remove_image(container_runner, &target_image);
+        let commands = runtime.run_commands();
+        assert_eq!(commands[0], "cat /etc/os-release");
+        assert!(commands.contains(&"pacman -S -y -y".to_string()));
+        assert!(commands.contains(&"pacman -S --needed --noconfirm fish".to_string()));
+    }
 
-        assert!(result.is_ok());
+    #[test]
+    fn test_build_image_fake_invalid_package() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let result = build_image(
+            &runtime,
+            "test_build_image_fake_invalid_package",
+            "ubuntu",
+            &None,
+            &["fish; rm -rf /".to_string()],
+            &get_distrobox_boost_test_image_prefix(),
+            false,
+        );
+        assert!(result.is_err());
+        assert_eq!(runtime.run_commands(), vec!["cat /etc/os-release"]);
+    }
+
+    #[test]
+    fn test_build_image_fake_closest_image() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let build = |target_image: &str, packages: &[&str]| {
+            let packages: Vec<String> = packages.iter().map(|p| p.to_string()).collect();
+            let run_count = runtime.run_commands().len();
+            build_image(
+                &runtime,
+                target_image,
+                "ubuntu",
+                &None,
+                &packages,
+                &get_distrobox_boost_test_image_prefix(),
+                false,
+            )
+            .unwrap();
+            runtime.run_commands()[run_count..].to_vec()
+        };
+
+        build("test_closest_image_toolchain", &["git", "gcc"]);
+        // the whole toolchain is reused, only the missing package is installed
+        assert_eq!(
+            build("test_closest_image_vim", &["gcc", "vim", "git"]),
+            ["cat /etc/os-release", "apt-get install -y vim"]
+        );
+        let image = runtime.image("test_closest_image_vim").unwrap();
+        assert_eq!(image.labels["package1"], "git;gcc;vim");
+
+        // images with packages that were not requested are never used
+        assert_eq!(
+            build("test_closest_image_htop", &["htop", "git"]),
+            ["cat /etc/os-release", "apt-get install -y htop"]
+        );
+        let image = runtime.image("test_closest_image_htop").unwrap();
+        assert_eq!(image.labels["package1"], "git;htop");
+        assert!(!image
+            .commands
+            .contains(&"apt-get install -y gcc".to_string()));
+    }
+
+    #[test]
+    fn test_diff_images_fake() {
+        let runtime = FakeRuntime::new()
+            .with_image("ubuntu", UBUNTU_OS_RELEASE)
+            .with_output("apt list", "Listing...\nbash/jammy,now 5.1 amd64\n")
+            .with_layer_output(
+                "apt-get install -y htop",
+                "apt list",
+                "Listing...\nbash/jammy,now 5.1 amd64\nhtop/jammy,now 3.0 amd64\n",
+            );
+        let image = build_image(
+            &runtime,
+            "test_diff_images_fake",
+            "ubuntu",
+            &None,
+            &["htop".to_string()],
+            &get_distrobox_boost_test_image_prefix(),
+            false,
+        )
+        .unwrap();
+
+        let changes = diff_images(&runtime, "ubuntu", &image, &None).unwrap();
+        assert_eq!(
+            changes,
+            vec![PackageChange {
+                name: "htop".to_string(),
+                old_version: None,
+                new_version: Some("3.0".to_string()),
+            }]
+        );
+        assert!(diff_images(&runtime, &image, &image, &None)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_build_image_fake_failed_install() {
+        let runtime = FakeRuntime::new()
+            .with_image("ubuntu", UBUNTU_OS_RELEASE)
+            .with_failure("install -y not-a-package");
+        let packages = vec!["not-a-package".to_string()];
+
+        let result = build_image(
+            &runtime,
+            "test_build_image_fake_failed_install",
+            "ubuntu",
+            &None,
+            &packages,
+            &get_distrobox_boost_test_image_prefix(),
+            true,
+        );
+        assert!(result.is_err());
+        assert!(runtime
+            .image("test_build_image_fake_failed_install")
+            .is_none());
+    }
+
+    #[test]
+    fn test_run_and_commit_image_fake() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let mut filter_map = HashMap::new();
+        filter_map.insert(
+            "test".to_string(),
+            Some("test_run_and_commit_image_fake".to_string()),
+        );
+        let filters = get_filter_vec(&filter_map);
+        let instructions = get_instructions(&filter_map);
+        let data = ContainerData {
+            runtime: &runtime,
+            cmd: &ContainerCommand::exec(&["touch", "/test_run_and_commit_image_fake"]),
+            base_image: "ubuntu",
+            target_image: "test_run_and_commit_image_fake",
+            filters: &filters.iter().map(AsRef::as_ref).collect::<Vec<&str>>(),
+            instructions: &instructions
+                .iter()
+                .map(AsRef::as_ref)
+                .collect::<Vec<&str>>(),
+            realtime_output: false,
+        };
+
+        run_and_commit_image(&data).unwrap();
+        let committed = runtime.image("test_run_and_commit_image_fake").unwrap();
+        assert_eq!(runtime.run_commands().len(), 1);
+
+        run_and_commit_image(&data).unwrap();
+        assert_eq!(runtime.run_commands().len(), 1);
+        assert!(runtime.calls().contains(&format!(
+            "tag {} test_run_and_commit_image_fake",
+            committed.id
+        )));
     }
 }
@@ -0,0 +1,341 @@
+// pinned images are kept alive by a container using them, so `prune` does not remove
+// them; the pins are recorded in a state file and the containers are labelled
+
+use crate::oci::container_command::ContainerCommand;
+use crate::oci::container_runtime::ContainerRuntime;
+use crate::oci::image_builder::get_seconds;
+use crate::utils::command_helper::CommandError;
+use crate::utils::digest::sha256_hex;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+const PIN_LABEL: &str = "distrobox-boost.pin";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinMode {
+    /// A detached `tail -f /dev/null` container restarted with the engine
+    Running,
+    /// A container that exited right away, nothing keeps running but
+    /// `system prune` removes it
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinEntry {
+    pub image: String,
+    pub container: String,
+    pub mode: PinMode,
+    pub pinned_at: u64,
+}
+
+/// "pin-" and the image name readable as a container name, the hash of the full name
+/// keeps images like `a/b:c` and `a-b/c` apart
+fn pin_container_name(image_name: &str) -> String {
+    let name: String = image_name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' | '-' => c,
+            _ => '-',
+        })
+        .collect();
+    format!("pin-{}-{}", name, &sha256_hex(image_name.as_bytes())[..8])
+}
+
+pub fn load_pins(state_path: &Path) -> io::Result<Vec<PinEntry>> {
+    match std::fs::read_to_string(state_path) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e),
+    }
+}
+
+fn save_pins(state_path: &Path, pins: &[PinEntry]) -> io::Result<()> {
+    if let Some(dir) = state_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(state_path, serde_json::to_string_pretty(pins)?)
+}
+
+fn remove_pin_container(runtime: &dyn ContainerRuntime, name: &str) -> Result<(), CommandError> {
+    if runtime.check_container_exists(name)? {
+        let _ = runtime.stop_container_with_args(name, &["-t", "0"]);
+        runtime.remove_container(name)?;
+    }
+    Ok(())
+}
+
+/// Pin `image_name`, pinning it again with the same mode does nothing
+pub fn pin_image(
+    runtime: &dyn ContainerRuntime,
+    state_path: &Path,
+    image_name: &str,
+    mode: PinMode,
+) -> Result<PinEntry, CommandError> {
+    let mut pins = load_pins(state_path)?;
+    let name = pin_container_name(image_name);
+    let pinned = pins.iter().find(|entry| entry.image == image_name);
+    if let Some(entry) = pinned {
+        if entry.mode == mode && runtime.check_container_exists(&entry.container)? {
+            return Ok(entry.clone());
+        }
+    }
+
+    // a container of another mode or left over from an older pin is replaced
+    if let Some(entry) = pinned.filter(|entry| entry.container != name) {
+        remove_pin_container(runtime, &entry.container)?;
+    }
+    remove_pin_container(runtime, &name)?;
+    let label = format!("{}={}", PIN_LABEL, image_name);
+    match mode {
+        PinMode::Running => runtime.run_container_with_args(
+            &name,
+            image_name,
+            &ContainerCommand::exec(&["tail", "-f", "/dev/null"]),
+            &["-d", "--restart", "unless-stopped", "--label", &label],
+            false,
+        )?,
+        PinMode::Stopped => runtime.run_container_with_args(
+            &name,
+            image_name,
+            &ContainerCommand::exec(&["true"]),
+            &["--label", &label],
+            false,
+        )?,
+    };
+
+    let entry = PinEntry {
+        image: image_name.to_string(),
+        container: name,
+        mode,
+        pinned_at: get_seconds(),
+    };
+    pins.retain(|pin| pin.image != image_name);
+    pins.push(entry.clone());
+    save_pins(state_path, &pins)?;
+    Ok(entry)
+}
+
+/// Unpin `image_name`, returns false if it was not pinned
+pub fn unpin_image(
+    runtime: &dyn ContainerRuntime,
+    state_path: &Path,
+    image_name: &str,
+) -> Result<bool, CommandError> {
+    let mut pins = load_pins(state_path)?;
+    let name = pin_container_name(image_name);
+    let recorded = pins
+        .iter()
+        .find(|pin| pin.image == image_name)
+        .map(|pin| pin.container.clone());
+    let pinned = recorded.is_some();
+    let mut container_exists = false;
+    // pins of older versions are recorded with another container name
+    for name in recorded.iter().filter(|c| **c != name).chain([&name]) {
+        container_exists |= runtime.check_container_exists(name)?;
+        remove_pin_container(runtime, name)?;
+    }
+    if pinned {
+        pins.retain(|pin| pin.image != image_name);
+        save_pins(state_path, &pins)?;
+    }
+    Ok(pinned || container_exists)
+}
+
+/// Every pin with whether its container still exists, it may be gone after
+/// `system prune` or a reboot
+pub fn get_pin_status(
+    runtime: &dyn ContainerRuntime,
+    state_path: &Path,
+) -> Result<Vec<(PinEntry, bool)>, CommandError> {
+    let mut status = vec![];
+    for entry in load_pins(state_path)? {
+        let exists = runtime.check_container_exists(&entry.container)?;
+        status.push((entry, exists));
+    }
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oci::fake_runtime::{FakeRuntime, UBUNTU_OS_RELEASE};
+    use std::path::PathBuf;
+
+    fn test_state_path(test_name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "distrobox_boost_test_{}_{}.json",
+            test_name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_pin_image() {
+        let runtime = FakeRuntime::new().with_image("ubuntu:latest", UBUNTU_OS_RELEASE);
+        let state_path = test_state_path("pin_image");
+        // Attempt to pin the ubuntu image
+        let result = pin_image(&runtime, &state_path, "ubuntu:latest", PinMode::Running);
+        assert!(result.is_ok(), "Failed to pin the ubuntu image");
+
+        // Pinning the same image again keeps the pin
+        let again = pin_image(&runtime, &state_path, "ubuntu:latest", PinMode::Running);
+        assert_eq!(result.unwrap(), again.unwrap());
+        assert_eq!(runtime.run_commands(), ["tail -f /dev/null"]);
+        let _ = std::fs::remove_file(&state_path);
+    }
+    #[test]
+    fn test_pin_image_nonexistent() {
+        let runtime = FakeRuntime::new();
+        let state_path = test_state_path("pin_image_nonexistent");
+        // Attempt to pin an image that does not exist, expect an error
+        let result = pin_image(
+            &runtime,
+            &state_path,
+            "localhost/nonexistent1",
+            PinMode::Running,
+        );
+        assert!(
+            result.is_err(),
+            "Should fail when attempting to pin a nonexistent image"
+        );
+        assert!(load_pins(&state_path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unpin_image() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let state_path = test_state_path("unpin_image");
+        // Pin the ubuntu image
+        pin_image(&runtime, &state_path, "ubuntu", PinMode::Running).unwrap();
+        // Unpin the pinned image
+        let unpin_result = unpin_image(&runtime, &state_path, "ubuntu");
+        assert!(unpin_result.unwrap(), "Failed to unpin the ubuntu image");
+        assert!(runtime.container_names().is_empty());
+        let _ = std::fs::remove_file(&state_path);
+    }
+    #[test]
+    fn test_unpin_image_nonexistent() {
+        let runtime = FakeRuntime::new();
+        let state_path = test_state_path("unpin_image_nonexistent");
+        // Unpinning an image that is not pinned does nothing
+        let result = unpin_image(&runtime, &state_path, "localhost/nonexistent1");
+        assert!(!result.unwrap(), "Nonexistent image should not be pinned");
+    }
+    #[test]
+    fn test_unpin_image_stopped() {
+        let runtime = FakeRuntime::new().with_image("alpine", UBUNTU_OS_RELEASE);
+        let state_path = test_state_path("unpin_image_stopped");
+        let image_name = "alpine";
+        let entry = pin_image(&runtime, &state_path, image_name, PinMode::Running).unwrap();
+
+        // Stop the pinned container
+        runtime
+            .stop_container_with_args(&entry.container, &["-t", "0"])
+            .unwrap();
+
+        // Attempt to unpin the stopped container, expect success
+        let result = unpin_image(&runtime, &state_path, image_name);
+        assert!(result.unwrap(), "Failed to unpin the stopped container");
+        assert!(runtime.container_names().is_empty());
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_pin_registry_fake() {
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let state_path = test_state_path("pin_registry_fake");
+
+        let entry = pin_image(&runtime, &state_path, "ubuntu", PinMode::Stopped).unwrap();
+        assert_eq!(entry.container, pin_container_name("ubuntu"));
+        assert!(entry.container.starts_with("pin-ubuntu-"));
+        assert_eq!(
+            pin_image(&runtime, &state_path, "ubuntu", PinMode::Stopped).unwrap(),
+            entry
+        );
+        assert_eq!(
+            runtime.run_commands(),
+            ["true"],
+            "pinning twice must not create a second container"
+        );
+        assert_eq!(load_pins(&state_path).unwrap(), vec![entry.clone()]);
+
+        // switching the mode replaces the container
+        let entry = pin_image(&runtime, &state_path, "ubuntu", PinMode::Running).unwrap();
+        assert_eq!(entry.mode, PinMode::Running);
+        assert_eq!(runtime.run_commands(), ["true", "tail -f /dev/null"]);
+        assert_eq!(load_pins(&state_path).unwrap().len(), 1);
+
+        // a pin whose container is gone is reported and repaired by pinning again
+        runtime.remove_container(&entry.container).unwrap();
+        assert_eq!(
+            get_pin_status(&runtime, &state_path).unwrap(),
+            vec![(entry.clone(), false)]
+        );
+        pin_image(&runtime, &state_path, "ubuntu", PinMode::Running).unwrap();
+        assert!(get_pin_status(&runtime, &state_path).unwrap()[0].1);
+
+        assert!(unpin_image(&runtime, &state_path, "ubuntu").unwrap());
+        assert!(!unpin_image(&runtime, &state_path, "ubuntu").unwrap());
+        assert!(load_pins(&state_path).unwrap().is_empty());
+        assert!(runtime.container_names().is_empty());
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_pin_container_name_collision() {
+        let name = pin_container_name("a/b:c");
+        assert_ne!(name, pin_container_name("a-b/c"));
+        assert_ne!(name, pin_container_name("a/b-c"));
+        assert!(name.starts_with("pin-a-b-c-"));
+        assert_eq!(name, pin_container_name("a/b:c"));
+
+        let runtime = FakeRuntime::new()
+            .with_image("a/b:c", UBUNTU_OS_RELEASE)
+            .with_image("a-b/c", UBUNTU_OS_RELEASE);
+        let state_path = test_state_path("pin_container_name_collision");
+        pin_image(&runtime, &state_path, "a/b:c", PinMode::Stopped).unwrap();
+        pin_image(&runtime, &state_path, "a-b/c", PinMode::Stopped).unwrap();
+        assert_eq!(runtime.container_names().len(), 2);
+        assert!(get_pin_status(&runtime, &state_path)
+            .unwrap()
+            .iter()
+            .all(|(_, exists)| *exists));
+        let _ = std::fs::remove_file(&state_path);
+    }
+
+    #[test]
+    fn test_unpin_image_recorded_name() {
+        // a pin recorded with the name of an older version
+        let runtime = FakeRuntime::new().with_image("ubuntu", UBUNTU_OS_RELEASE);
+        let state_path = test_state_path("unpin_image_recorded_name");
+        runtime
+            .run_container_with_args(
+                "pin-ubuntu",
+                "ubuntu",
+                &ContainerCommand::exec(&["true"]),
+                &[],
+                false,
+            )
+            .unwrap();
+        let entry = PinEntry {
+            image: "ubuntu".to_string(),
+            container: "pin-ubuntu".to_string(),
+            mode: PinMode::Stopped,
+            pinned_at: 0,
+        };
+        save_pins(&state_path, std::slice::from_ref(&entry)).unwrap();
+        assert_eq!(
+            pin_image(&runtime, &state_path, "ubuntu", PinMode::Stopped).unwrap(),
+            entry
+        );
+
+        assert!(unpin_image(&runtime, &state_path, "ubuntu").unwrap());
+        assert!(runtime.container_names().is_empty());
+        assert!(load_pins(&state_path).unwrap().is_empty());
+        let _ = std::fs::remove_file(&state_path);
+    }
+}
@@ -0,0 +1,131 @@
+// installed packages of the built images, kept as <manifest dir>/<container>.json
+
+use crate::distrobox::parser::assemble::ContainerAssembleData;
+use crate::oci::container_runtime::ContainerRuntime;
+use crate::oci::image_builder::{get_image_package_manager, list_image_packages};
+use crate::utils::command_helper::CommandError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageManifest {
+    pub container: String,
+    pub image: String,
+    /// Id of `image` when the manifest was recorded
+    pub image_id: String,
+    pub package_manager: String,
+    pub packages: Vec<InstalledPackage>,
+}
+
+pub fn get_manifest_path(dir: &Path, container: &str) -> PathBuf {
+    dir.join(format!("{}.json", container))
+}
+
+pub fn create_package_manifest(
+    runtime: &dyn ContainerRuntime,
+    container: &str,
+    image: &str,
+    request_package_manager: &Option<String>,
+) -> Result<PackageManifest, CommandError> {
+    let package_manager = get_image_package_manager(runtime, image, request_package_manager)?;
+    let mut packages: Vec<InstalledPackage> =
+        list_image_packages(runtime, image, &package_manager)?
+            .into_iter()
+            .map(|(name, version)| InstalledPackage { name, version })
+            .collect();
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(PackageManifest {
+        container: container.to_string(),
+        image: image.to_string(),
+        image_id: runtime.inspect_image(image, ".Id")?.trim().to_string(),
+        package_manager,
+        packages,
+    })
+}
+
+pub fn write_package_manifest(dir: &Path, manifest: &PackageManifest) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let content = serde_json::to_string_pretty(manifest)?;
+    std::fs::write(get_manifest_path(dir, &manifest.container), content)
+}
+
+pub fn read_package_manifest(dir: &Path, container: &str) -> io::Result<PackageManifest> {
+    let content = std::fs::read_to_string(get_manifest_path(dir, container))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Record the manifest of every container in `data`, whose images are already built
+pub fn record_package_manifests(
+    runtime: &dyn ContainerRuntime,
+    data: &HashMap<String, ContainerAssembleData>,
+    dir: &Path,
+) -> Result<(), CommandError> {
+    for (name, value) in data {
+        let manifest =
+            create_package_manifest(runtime, name, &value.image, &value.package_manager)?;
+        write_package_manifest(dir, &manifest).map_err(|e| CommandError {
+            stdout: String::new(),
+            stderr: format!("Failed to write package manifest of {}: {}", name, e),
+            status: Some(1),
+            inner: Some(e),
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oci::fake_runtime::{FakeRuntime, UBUNTU_OS_RELEASE};
+
+    #[test]
+    fn test_record_package_manifests() {
+        let runtime = FakeRuntime::new()
+            .with_image("ubuntu", UBUNTU_OS_RELEASE)
+            .with_output(
+                "apt list",
+                "Listing...\nopenssl/jammy,now 3.0.2 amd64 [installed]\nbash/jammy,now 5.1 amd64 [installed]\n",
+            );
+        let dir = std::env::temp_dir().join(format!(
+            "distrobox_boost_test_manifest_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let data = HashMap::from([(
+            "dev".to_string(),
+            ContainerAssembleData {
+                image: "ubuntu".to_string(),
+                ..Default::default()
+            },
+        )]);
+
+        record_package_manifests(&runtime, &data, &dir).unwrap();
+        let manifest = read_package_manifest(&dir, "dev").unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(manifest.image, "ubuntu");
+        assert_eq!(manifest.image_id, runtime.image("ubuntu").unwrap().id);
+        assert_eq!(manifest.package_manager, "apt");
+        assert_eq!(
+            manifest.packages,
+            vec![
+                InstalledPackage {
+                    name: "bash".to_string(),
+                    version: "5.1".to_string()
+                },
+                InstalledPackage {
+                    name: "openssl".to_string(),
+                    version: "3.0.2".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -1,3 +1,4 @@
 pub mod command_helper;
+pub mod digest;
 pub mod ini;
 pub mod mutex_lock;
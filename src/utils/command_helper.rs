@@ -1,8 +1,30 @@
+use std::cell::RefCell;
 use std::error::Error;
 use std::io::{self, BufRead, BufReader};
 use std::process::{Command, Stdio};
 use std::{fmt, thread};
 
+thread_local! {
+    static OUTPUT_PREFIX: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Prefix every line printed by commands run from the current thread, e.g. "[arch] "
+pub fn set_output_prefix(prefix: &str) {
+    OUTPUT_PREFIX.with(|p| *p.borrow_mut() = prefix.to_string());
+}
+
+fn get_output_prefix() -> String {
+    OUTPUT_PREFIX.with(|p| p.borrow().clone())
+}
+
+/// Print `message` to stdout with the prefix of the current thread on every line
+pub fn print_with_prefix(message: &str) {
+    let prefix = get_output_prefix();
+    for line in message.lines() {
+        println!("{}{}", prefix, line);
+    }
+}
+
 #[derive(Debug)]
 pub struct CommandError {
     pub stdout: String,
@@ -23,6 +45,17 @@ impl Error for CommandError {
     }
 }
 
+impl From<io::Error> for CommandError {
+    fn from(e: io::Error) -> Self {
+        CommandError {
+            stdout: String::new(),
+            stderr: e.to_string(),
+            status: None,
+            inner: Some(e),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CommandOutput {
     pub stdout: String,
@@ -50,7 +83,8 @@ fn _run_command(
     inherit: bool,
     realtime_output: bool,
 ) -> Result<CommandOutput, CommandError> {
-    println!("Run command: {} {}", command_name, args.join(" "));
+    let prefix = get_output_prefix();
+    println!("{}Run command: {} {}", prefix, command_name, args.join(" "));
     let mut collected_stdout = String::new();
     let mut collected_stderr = String::new();
 
@@ -80,12 +114,13 @@ fn _run_command(
         let stdout_reader = BufReader::new(stdout);
         let stderr_reader = BufReader::new(stderr);
 
+        let stdout_prefix = prefix.clone();
         let stdout_handle = thread::spawn(move || {
             let mut collected_stdout = String::new();
             for line in stdout_reader.lines() {
                 let l = line.unwrap();
                 if realtime_output {
-                    println!("{}", l);
+                    println!("{}{}", stdout_prefix, l);
                 }
                 collected_stdout.push_str(&l);
                 collected_stdout.push('\n');
@@ -98,7 +133,7 @@ fn _run_command(
             for line in stderr_reader.lines() {
                 let l = line.unwrap();
                 if realtime_output {
-                    eprintln!("{}", l);
+                    eprintln!("{}{}", prefix, l);
                 }
                 collected_stderr.push_str(&l);
                 collected_stderr.push('\n');
@@ -2,58 +2,51 @@
 
 use std::collections::HashMap;
 
-pub fn from_ini(input: &str) -> Vec<(String, Vec<(String, String)>)> {
-    #[inline]
-    fn remove_quotes(s: &str) -> String {
-        let s = s.trim();
-        let chars: Vec<char> = s.chars().collect();
-        let len = chars.len();
-
-        if len >= 2 {
-            let (first, last) = (chars[0], chars[len - 1]);
-            let count = match (first, last) {
-                ('\'', '\'') => chars.iter().filter(|&c| *c == '\'').count(),
-                ('"', '"') => chars.iter().filter(|&c| *c == '"').count(),
-                _ => return s.to_string(),
-            };
-
-            if count <= 2 {
-                return s[1..len - 1].to_string();
-            }
+fn remove_quotes(s: &str) -> String {
+    let s = s.trim();
+    let chars: Vec<char> = s.chars().collect();
+    let len = chars.len();
+
+    if len >= 2 {
+        let (first, last) = (chars[0], chars[len - 1]);
+        let count = match (first, last) {
+            ('\'', '\'') => chars.iter().filter(|&c| *c == '\'').count(),
+            ('"', '"') => chars.iter().filter(|&c| *c == '"').count(),
+            _ => return s.to_string(),
+        };
+
+        if count <= 2 {
+            return s[1..s.len() - 1].to_string();
         }
+    }
+
+    s.to_string()
+}
 
-        s.to_string()
+/// Byte offset of a `#` or `;` comment that is not quoted or escaped
+fn find_comment(line: &str) -> Option<usize> {
+    let mut in_single_quote = false;
+    let mut in_double_quote = false;
+    let mut prev_char = '\0';
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '#' if !in_single_quote && !in_double_quote && prev_char != '\\' => return Some(i),
+            ';' if !in_single_quote && !in_double_quote && prev_char != '\\' => return Some(i),
+            '\'' if !in_double_quote && prev_char != '\\' => in_single_quote = !in_single_quote,
+            '"' if !in_single_quote && prev_char != '\\' => in_double_quote = !in_double_quote,
+            _ => (),
+        }
+        prev_char = c;
     }
+    None
+}
+
+pub fn from_ini(input: &str) -> Vec<(String, Vec<(String, String)>)> {
     let mut result: Vec<(String, Vec<(String, String)>)> = Vec::new();
     let mut current_section = String::new();
 
     for line in input.lines() {
-        fn find_comment(line: &str) -> Option<usize> {
-            let mut in_single_quote = false;
-            let mut in_double_quote = false;
-            let mut prev_char = '\0';
-
-            for (i, c) in line.chars().enumerate() {
-                match c {
-                    '#' if !in_single_quote && !in_double_quote && prev_char != '\\' => {
-                        return Some(i)
-                    }
-                    ';' if !in_single_quote && !in_double_quote && prev_char != '\\' => {
-                        return Some(i)
-                    }
-                    '\'' if !in_double_quote && prev_char != '\\' => {
-                        in_single_quote = !in_single_quote
-                    }
-                    '"' if !in_single_quote && prev_char != '\\' => {
-                        in_double_quote = !in_double_quote
-                    }
-                    _ => (),
-                }
-                prev_char = c;
-            }
-            None
-        }
-
         let line = match find_comment(line) {
             Some(pos) => &line[..pos],
             None => line,
@@ -70,8 +63,9 @@ pub fn from_ini(input: &str) -> Vec<(String, Vec<(String, String)>)> {
         } else if let Some(eq_pos) = line.find('=') {
             let key = line[..eq_pos].trim();
             let value = line[eq_pos + 1..].trim();
+            // a key before the first section belongs to nothing, validation reports it
             if current_section.is_empty() {
-                panic!("Key-value pair outside of a section: {}", line);
+                continue;
             }
             if let Some((_, section)) = result.iter_mut().find(|(name, _)| name == &current_section)
             {
@@ -83,7 +77,7 @@ pub fn from_ini(input: &str) -> Vec<(String, Vec<(String, String)>)> {
     result
 }
 
-pub fn to_ini(data: &Vec<(String, Vec<(String, String)>)>) -> String {
+pub fn to_ini(data: &[(String, Vec<(String, String)>)]) -> String {
     let mut output = String::new();
 
     for (section_name, section_data) in data.iter() {
@@ -116,6 +110,308 @@ pub fn merge_ini(
     merged_data
 }
 
+/// A `key=value` line split so that `prefix + value + suffix` is the line as written
+#[derive(Clone, Debug, PartialEq)]
+struct IniEntry {
+    key: String,
+    /// Everything up to the value, e.g. "key = "
+    prefix: String,
+    /// The value with its quotes
+    raw_value: String,
+    /// Whitespace and comment after the value
+    suffix: String,
+}
+
+impl IniEntry {
+    fn value(&self) -> String {
+        remove_quotes(&self.raw_value)
+    }
+
+    /// Keep the quote style of the line, values that would not survive bare get quoted
+    fn set_value(&mut self, value: &str) {
+        let quote = self
+            .raw_value
+            .chars()
+            .next()
+            .filter(|c| (*c == '"' || *c == '\'') && self.value() != self.raw_value.trim());
+        self.raw_value = quote_ini_value(value, quote);
+    }
+}
+
+/// Quote `value` with `quote` or a quote character it does not contain, there is no
+/// escaping so a value with both quote characters is written bare
+fn quote_ini_value(value: &str, quote: Option<char>) -> String {
+    let needs_quotes = value.contains(['#', ';', '"', '\'']) || value.trim() != value;
+    let quote = quote.filter(|q| !value.contains(*q)).or_else(|| {
+        ['"', '\'']
+            .into_iter()
+            .find(|q| !value.contains(*q))
+            .filter(|_| needs_quotes)
+    });
+    match quote {
+        Some(quote) => format!("{}{}{}", quote, value, quote),
+        None => value.to_string(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum IniLine {
+    /// Blank lines, comments and anything else that is kept as is
+    Text(String),
+    Section {
+        raw: String,
+        name: String,
+    },
+    Entry(IniEntry),
+}
+
+fn parse_line(line: &str) -> IniLine {
+    let content_end = find_comment(line).unwrap_or(line.len());
+    let content = line[..content_end].trim();
+    if content.starts_with('[') && content.ends_with(']') {
+        return IniLine::Section {
+            raw: line.to_string(),
+            name: content[1..content.len() - 1].to_string(),
+        };
+    }
+    let Some(eq_pos) = line[..content_end].find('=') else {
+        return IniLine::Text(line.to_string());
+    };
+    let after_eq = &line[eq_pos + 1..content_end];
+    let value_start = eq_pos + 1 + (after_eq.len() - after_eq.trim_start().len());
+    let value_end = (eq_pos + 1 + after_eq.trim_end().len()).max(value_start);
+    IniLine::Entry(IniEntry {
+        key: remove_quotes(&line[..eq_pos]),
+        prefix: line[..value_start].to_string(),
+        raw_value: line[value_start..value_end].to_string(),
+        suffix: line[value_end..].to_string(),
+    })
+}
+
+/// A line of an ini file as a validator sees it, lines are numbered from 1
+#[derive(Clone, Debug, PartialEq)]
+pub enum IniItem {
+    Section {
+        line: usize,
+        name: String,
+    },
+    Entry {
+        line: usize,
+        section: Option<String>,
+        key: String,
+        value: String,
+    },
+    /// Not blank, not a comment, not a section and not `key=value`
+    Invalid {
+        line: usize,
+        section: Option<String>,
+        text: String,
+    },
+}
+
+/// An ini file that is written back as it was read, except for the values that are set,
+/// so comments, blank lines, order and quoting of a hand-written file survive
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IniDocument {
+    lines: Vec<IniLine>,
+}
+
+impl IniDocument {
+    pub fn parse(input: &str) -> Self {
+        IniDocument {
+            lines: input.split('\n').map(parse_line).collect(),
+        }
+    }
+
+    /// Section of every line, `None` before the first section
+    fn line_sections(&self) -> Vec<Option<&str>> {
+        let mut current = None;
+        self.lines
+            .iter()
+            .map(|line| {
+                if let IniLine::Section { name, .. } = line {
+                    current = Some(name.as_str());
+                }
+                current
+            })
+            .collect()
+    }
+
+    fn entry_indexes(&self, section: &str, key: &str) -> Vec<usize> {
+        self.line_sections()
+            .iter()
+            .enumerate()
+            .filter(|(i, line_section)| {
+                *line_section == &Some(section)
+                    && matches!(&self.lines[*i], IniLine::Entry(entry) if entry.key == key)
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Names of the sections in the order they first appear
+    pub fn sections(&self) -> Vec<String> {
+        let mut sections: Vec<String> = vec![];
+        for line in &self.lines {
+            if let IniLine::Section { name, .. } = line {
+                if !sections.contains(name) {
+                    sections.push(name.clone());
+                }
+            }
+        }
+        sections
+    }
+
+    pub fn has_section(&self, section: &str) -> bool {
+        self.sections().iter().any(|name| name == section)
+    }
+
+    /// Every value of `key` in `section`, duplicate sections included
+    pub fn get_values(&self, section: &str, key: &str) -> Vec<String> {
+        self.entry_indexes(section, key)
+            .into_iter()
+            .filter_map(|i| match &self.lines[i] {
+                IniLine::Entry(entry) => Some(entry.value()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Keys and values of `section` in file order, values as written with their quotes
+    pub fn raw_entries(&self, section: &str) -> Vec<(String, String)> {
+        self.line_sections()
+            .iter()
+            .zip(&self.lines)
+            .filter_map(|(line_section, line)| match line {
+                IniLine::Entry(entry) if *line_section == Some(section) => {
+                    Some((entry.key.clone(), entry.raw_value.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Sections, entries and unparsable lines with their line numbers
+    pub fn items(&self) -> Vec<IniItem> {
+        self.line_sections()
+            .iter()
+            .zip(&self.lines)
+            .enumerate()
+            .filter_map(|(i, (line_section, line))| {
+                let section = line_section.map(|name| name.to_string());
+                match line {
+                    IniLine::Section { name, .. } => Some(IniItem::Section {
+                        line: i + 1,
+                        name: name.clone(),
+                    }),
+                    IniLine::Entry(entry) => Some(IniItem::Entry {
+                        line: i + 1,
+                        section,
+                        key: entry.key.clone(),
+                        value: entry.value(),
+                    }),
+                    IniLine::Text(text) => {
+                        let content_end = find_comment(text).unwrap_or(text.len());
+                        (!text[..content_end].trim().is_empty()).then(|| IniItem::Invalid {
+                            line: i + 1,
+                            section,
+                            text: text.trim().to_string(),
+                        })
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Make `values` the values of `key` in `section`: the existing lines are
+    /// rewritten in place, extra lines removed and missing ones added after the
+    /// last entry of the section, the section is appended if it does not exist
+    pub fn set_values(&mut self, section: &str, key: &str, values: &[String]) {
+        let indexes = self.entry_indexes(section, key);
+        for (i, value) in indexes.iter().zip(values) {
+            if let IniLine::Entry(entry) = &mut self.lines[*i] {
+                if entry.value() != *value {
+                    entry.set_value(value);
+                }
+            }
+        }
+        for i in indexes.iter().skip(values.len()).rev() {
+            self.lines.remove(*i);
+        }
+        if values.len() <= indexes.len() {
+            return;
+        }
+
+        let new_lines = values[indexes.len()..].iter().map(|value| {
+            IniLine::Entry(IniEntry {
+                key: key.to_string(),
+                prefix: format!("{}=", key),
+                raw_value: quote_ini_value(value, None),
+                suffix: String::new(),
+            })
+        });
+        let line_sections = self.line_sections();
+        let position = line_sections
+            .iter()
+            .enumerate()
+            .filter(|(i, line_section)| {
+                *line_section == &Some(section)
+                    && matches!(&self.lines[*i], IniLine::Entry(_) | IniLine::Section { .. })
+            })
+            .map(|(i, _)| i + 1)
+            .next_back();
+        match position {
+            Some(position) => {
+                self.lines.splice(position..position, new_lines);
+            }
+            None => {
+                let mut lines = vec![IniLine::Section {
+                    raw: format!("[{}]", section),
+                    name: section.to_string(),
+                }];
+                lines.extend(new_lines);
+                self.append(IniDocument { lines });
+            }
+        }
+    }
+
+    /// Add the lines of `other` at the end, separated by a blank line
+    pub fn append(&mut self, other: IniDocument) {
+        let mut text = self.to_string();
+        if text.trim().is_empty() {
+            text.clear();
+        } else {
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+            if !text.ends_with("\n\n") {
+                text.push('\n');
+            }
+        }
+        text.push_str(&other.to_string());
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        *self = IniDocument::parse(&text);
+    }
+}
+
+impl std::fmt::Display for IniDocument {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let lines: Vec<String> = self
+            .lines
+            .iter()
+            .map(|line| match line {
+                IniLine::Text(raw) | IniLine::Section { raw, .. } => raw.clone(),
+                IniLine::Entry(entry) => {
+                    format!("{}{}{}", entry.prefix, entry.raw_value, entry.suffix)
+                }
+            })
+            .collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -229,4 +525,95 @@ key2=value2
             "The output does not match the expected output."
         );
     }
+
+    #[test]
+    fn test_ini_document_round_trip() {
+        let content = sample_ini_content()
+            + "key_outside = 1\n[quoted]\nvalue = \"a # b\"  ; comment\r\nother='x'\n\n";
+        let document = IniDocument::parse(&content);
+        assert_eq!(document.to_string(), content);
+        assert_eq!(
+            document.sections(),
+            vec![
+                "riscv64-debian",
+                "another-section",
+                "empty-section",
+                "quoted"
+            ]
+        );
+        assert_eq!(
+            document.get_values("riscv64-debian", "additional_packages"),
+            vec!["neofetch locales", "git"]
+        );
+        assert_eq!(document.get_values("quoted", "value"), vec!["a # b"]);
+        assert_eq!(
+            document.get_values("another-section", "key1"),
+            vec!["value1"]
+        );
+    }
+
+    #[test]
+    fn test_ini_document_set_values() {
+        let mut document = IniDocument::parse(
+            "# my boxes\n[dev]\nimage = \"ubuntu:22.04\" # pinned\npull=true\nadditional_packages=git\nadditional_packages=vim\n\n# tools\n[tools]\nimage=alpine\n",
+        );
+        document.set_values("dev", "image", &["distrobox-boost/release/dev".to_string()]);
+        document.set_values("dev", "pull", &["true".to_string()]);
+        document.set_values("dev", "additional_packages", &["git vim".to_string()]);
+        document.set_values("tools", "pull", &["false".to_string()]);
+        document.set_values("new", "image", &["fedora".to_string()]);
+        assert_eq!(
+            document.to_string(),
+            "# my boxes\n[dev]\nimage = \"distrobox-boost/release/dev\" # pinned\npull=true\nadditional_packages=git vim\n\n# tools\n[tools]\nimage=alpine\npull=false\n\n[new]\nimage=fedora\n"
+        );
+
+        document.set_values("tools", "pull", &[]);
+        assert!(document.get_values("tools", "pull").is_empty());
+        document.set_values("dev", "init_hooks", &["echo 'hi'; true".to_string()]);
+        assert_eq!(
+            document.get_values("dev", "init_hooks"),
+            vec!["echo 'hi'; true"]
+        );
+    }
+
+    #[test]
+    fn test_ini_document_set_values_with_quotes() {
+        let values = [
+            "echo \"hi\"",
+            "echo 'hi'",
+            "'quoted'",
+            "\"quoted\"",
+            "say \"hi\" # not a comment",
+            "it's; fine",
+            " padded ",
+        ];
+        for value in values {
+            for line in [
+                "init_hooks='old' # note",
+                "init_hooks=\"old\"",
+                "init_hooks=old",
+            ] {
+                let mut document = IniDocument::parse(&format!("[dev]\n{}\n", line));
+                document.set_values("dev", "init_hooks", &[value.to_string()]);
+                let content = document.to_string();
+
+                // read back by both parsers
+                assert_eq!(
+                    IniDocument::parse(&content).get_values("dev", "init_hooks"),
+                    vec![value],
+                    "{}",
+                    content
+                );
+                assert_eq!(
+                    from_ini(&content),
+                    vec![(
+                        "dev".to_string(),
+                        vec![("init_hooks".to_string(), value.to_string())]
+                    )],
+                    "{}",
+                    content
+                );
+            }
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
 
 pub struct SynchronizedMap<K: Eq + Hash> {
     map: Mutex<HashMap<K, Arc<Mutex<()>>>>,
@@ -18,17 +18,99 @@ impl<K: Eq + Hash> SynchronizedMap<K> {
         F: FnOnce() -> R,
         K: Clone,
     {
-        let mut map = self.map.lock().unwrap();
-
-        let mutex = if let Some(m) = map.get(&key) {
-            m.clone()
-        } else {
-            let m = Arc::new(Mutex::new(()));
-            map.insert(key, m.clone());
-            m
+        // only hold the map lock while looking up the key, so different keys run concurrently
+        let mutex = {
+            let mut map = self.map.lock().unwrap();
+            map.entry(key)
+                .or_insert_with(|| Arc::new(Mutex::new(())))
+                .clone()
         };
 
         let _guard = mutex.lock().unwrap();
         func()
     }
 }
+
+pub struct Semaphore {
+    permits: Mutex<usize>,
+    condvar: Condvar,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            permits: Mutex::new(permits.max(1)),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn execute<F, R>(&self, func: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        {
+            let mut permits = self.permits.lock().unwrap();
+            while *permits == 0 {
+                permits = self.condvar.wait(permits).unwrap();
+            }
+            *permits -= 1;
+        }
+        struct Release<'a>(&'a Semaphore);
+        impl Drop for Release<'_> {
+            fn drop(&mut self) {
+                *self.0.permits.lock().unwrap() += 1;
+                self.0.condvar.notify_one();
+            }
+        }
+        let _release = Release(self);
+        func()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_synchronized_map_different_keys_run_concurrently() {
+        let map = &SynchronizedMap::new();
+        let (tx, rx) = mpsc::channel();
+        let received = thread::scope(|s| {
+            let waiter = s.spawn(move || {
+                map.execute("a".to_string(), || {
+                    rx.recv_timeout(Duration::from_secs(5)).is_ok()
+                })
+            });
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                map.execute("b".to_string(), || tx.send(()).unwrap());
+            });
+            waiter.join().unwrap()
+        });
+        assert!(received);
+    }
+
+    #[test]
+    fn test_semaphore_limits_concurrency() {
+        let semaphore = Semaphore::new(2);
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    semaphore.execute(|| {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_running.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(20));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                });
+            }
+        });
+        assert!(max_running.load(Ordering::SeqCst) <= 2);
+    }
+}